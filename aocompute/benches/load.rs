@@ -0,0 +1,22 @@
+use aocompute::{Instruction, Memory};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+fn load(c: &mut Criterion) {
+    let memory = Memory::new((0..100_000).map(|i| match i % 3 {
+        0 => Instruction::Noop(i),
+        1 => Instruction::Accumulate(-i),
+        _ => Instruction::Jump(i % 1000),
+    }));
+    let (text, bytes) = (memory.to_string(), memory.to_bytes());
+    let mut group = c.benchmark_group("load");
+    group.bench_function("parse", |b| {
+        b.iter(|| black_box(text.parse::<Memory>().unwrap()))
+    });
+    group.bench_function("from_bytes", |b| {
+        b.iter(|| black_box(Memory::from_bytes(&bytes).unwrap()))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, load);
+criterion_main!(benches);
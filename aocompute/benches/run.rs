@@ -0,0 +1,41 @@
+use aocompute::{fast::Compiled, immutable::ImmutableMemory, Instruction};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// Runs every instruction once, then tries to jump back to the start.
+fn program(len: usize) -> Vec<Instruction> {
+    let mut instructions: Vec<Instruction> = (0..len)
+        .map(|i| match i % 3 {
+            0 => Instruction::Noop(1),
+            1 => Instruction::Accumulate(1),
+            _ => Instruction::Jump(1),
+        })
+        .collect();
+    instructions.push(Instruction::Jump(-(len as isize)));
+    instructions
+}
+
+fn run(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run");
+    for len in [100, 1_000, 10_000].iter() {
+        let immutable_memory = ImmutableMemory::new(program(*len));
+        group.bench_with_input(BenchmarkId::new("run", len), &immutable_memory, |b, m| {
+            b.iter(|| black_box(m.clone().run()))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("run_fast", len),
+            &immutable_memory,
+            |b, m| b.iter(|| black_box(m.clone().run_fast())),
+        );
+        // Without decoding each time, as when running many variants of one program
+        let compiled = Compiled::new(&immutable_memory.memory.instructions);
+        group.bench_with_input(
+            BenchmarkId::new("compiled", len),
+            &immutable_memory,
+            |b, m| b.iter(|| black_box(compiled.run(m.clone()))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, run);
+criterion_main!(benches);
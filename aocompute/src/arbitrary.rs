@@ -0,0 +1,169 @@
+//! proptest generators for instructions and programs, with the `proptest` feature.
+//! Programs are well-formed - every jump lands inside the program or on its end - and can
+//! be made to always halt, or always loop.
+use crate::{Instruction, Memory};
+use proptest::prelude::*;
+use std::ops::Range;
+
+/// What sort of control flow to generate.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Shape {
+    /// Any jumps, and `in` with a few queued inputs
+    Any,
+    /// Only forward jumps and no `in`, so every run halts. `acc` operands are cut down if need
+    /// be, so that the accumulator can't overflow on the way.
+    Halting,
+    /// Forward jumps which stay inside the program, then a final jump backwards,
+    /// so every run loops forever
+    Looping,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Params {
+    pub shape: Shape,
+    /// How many instructions - at least 1 for `Looping`
+    pub len: Range<usize>,
+    /// `acc`, `nop`, `in` and `out` operands and inputs are at most this far from 0
+    pub operand: isize,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Self {
+            shape: Shape::Any,
+            len: 0..24,
+            operand: 100,
+        }
+    }
+}
+
+impl Params {
+    pub fn with_shape(mut self, shape: Shape) -> Self {
+        self.shape = shape;
+        self
+    }
+    pub fn with_len(mut self, len: Range<usize>) -> Self {
+        self.len = len;
+        self
+    }
+    pub fn with_operand(mut self, operand: isize) -> Self {
+        self.operand = operand;
+        self
+    }
+}
+
+/// Any instruction, with any operand.
+impl Arbitrary for Instruction {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            any::<isize>().prop_map(Instruction::Noop),
+            any::<isize>().prop_map(Instruction::Accumulate),
+            any::<isize>().prop_map(Instruction::Jump),
+            any::<isize>().prop_map(Instruction::Input),
+            any::<isize>().prop_map(Instruction::Output),
+        ]
+        .boxed()
+    }
+}
+
+/// A jump from `index` to somewhere in `targets`.
+fn jump(index: usize, targets: Range<usize>) -> BoxedStrategy<Instruction> {
+    targets
+        .prop_map(move |target| Instruction::Jump(target as isize - index as isize))
+        .boxed()
+}
+
+/// The instruction at `index` of a `len` long program.
+fn instruction_at(index: usize, len: usize, params: &Params) -> BoxedStrategy<Instruction> {
+    let operand = -params.operand..=params.operand;
+    // Every instruction runs at most once, so this many `acc`s can't overflow
+    let acc = match params.shape {
+        Shape::Halting => params.operand.min(isize::MAX / len as isize),
+        _ => params.operand,
+    };
+    let jump = match params.shape {
+        Shape::Any => jump(index, 0..len + 1),
+        Shape::Halting => jump(index, index + 1..len + 1),
+        Shape::Looping if index + 1 == len => return jump(index, 0..len),
+        Shape::Looping => jump(index, index + 1..len),
+    };
+    let straight = prop_oneof![
+        operand.clone().prop_map(Instruction::Noop),
+        (-acc..=acc).prop_map(Instruction::Accumulate),
+        operand.clone().prop_map(Instruction::Output),
+    ];
+    match params.shape {
+        Shape::Any => prop_oneof![straight, jump, operand.prop_map(Instruction::Input)].boxed(),
+        _ => prop_oneof![straight, jump].boxed(),
+    }
+}
+
+/// A well-formed program starting from index 0, shaped by `Params`.
+impl Arbitrary for Memory {
+    type Parameters = Params;
+    type Strategy = BoxedStrategy<Self>;
+    fn arbitrary_with(params: Params) -> Self::Strategy {
+        let inputs = match params.shape {
+            Shape::Any => 0..4,
+            _ => 0..1,
+        };
+        let input = -params.operand..=params.operand;
+        let len = match params.shape {
+            Shape::Looping => params.len.start.max(1)..params.len.end.max(2),
+            _ => params.len.clone(),
+        };
+        len.prop_flat_map(move |len| {
+            let instructions: Vec<_> = (0..len)
+                .map(|index| instruction_at(index, len, &params))
+                .collect();
+            (
+                instructions,
+                prop::collection::vec(input.clone(), inputs.clone()),
+            )
+        })
+        .prop_map(|(instructions, inputs)| {
+            let mut memory = Memory::new(instructions);
+            memory.inputs = inputs.into();
+            memory
+        })
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{steps::Progress, Status};
+
+    proptest! {
+        #[test]
+        fn well_formed(memory in any::<Memory>()) {
+            let len = memory.instructions.len() as isize;
+            for (index, instruction) in memory.instructions.iter().enumerate() {
+                if let Instruction::Jump(j) = instruction {
+                    prop_assert!((0..=len).contains(&(index as isize + j)));
+                }
+            }
+        }
+        #[test]
+        fn halting(
+            memory in any_with::<Memory>(Params::default().with_shape(Shape::Halting)),
+            wide in any_with::<Memory>(
+                Params::default().with_shape(Shape::Halting).with_operand(isize::MAX)
+            ),
+        ) {
+            let status = wide.steps().last().and_then(Progress::status);
+            prop_assert!(matches!(status, Some(Status::Halted(_))));
+            let status = memory.steps().last().and_then(Progress::status);
+            prop_assert!(matches!(status, Some(Status::Halted(_))));
+        }
+        #[test]
+        fn looping(
+            memory in any_with::<Memory>(Params::default().with_shape(Shape::Looping))
+        ) {
+            prop_assert!(memory.steps().take(1000).all(|p| matches!(p, Progress::At(_))));
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use crate::{isa::Fault, FaultKind, Instruction, Memory};
+use num_bigint::BigInt;
+use std::{
+    collections::{HashSet, VecDeque},
+    convert::TryFrom,
+    fmt,
+};
+
+/// What happens when an `acc` (or `in`) would take the accumulator outside its width.
+/// `Memory` always behaves as `Checked` with an `isize` accumulator.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Mode {
+    /// Overflow is a `Fault::Overflow`
+    Checked,
+    /// Wrap around two's complement
+    Wrapping,
+    /// Stick at the largest or smallest value
+    Saturating,
+}
+
+/// An accumulator of some width. Operands are always `isize`, as in `Instruction`.
+pub trait Accumulator: Clone + fmt::Debug + fmt::Display + Eq + Default {
+    /// `None` if the result doesn't fit.
+    fn checked_add(&self, operand: isize) -> Option<Self>;
+    fn wrapping_add(&self, operand: isize) -> Self;
+    fn saturating_add(&self, operand: isize) -> Self;
+    /// `None` if the result doesn't fit and `mode` is `Checked`.
+    fn add(&self, operand: isize, mode: Mode) -> Option<Self> {
+        match mode {
+            Mode::Checked => self.checked_add(operand),
+            Mode::Wrapping => Some(self.wrapping_add(operand)),
+            Mode::Saturating => Some(self.saturating_add(operand)),
+        }
+    }
+}
+
+// Up to 64 bits, the sum of an accumulator and an operand always fits in an i128
+macro_rules! narrow_accumulator {
+    ($($t:ty),*) => {$(
+        impl Accumulator for $t {
+            fn checked_add(&self, operand: isize) -> Option<Self> {
+                Self::try_from(*self as i128 + operand as i128).ok()
+            }
+            fn wrapping_add(&self, operand: isize) -> Self {
+                (*self as i128 + operand as i128) as Self
+            }
+            fn saturating_add(&self, operand: isize) -> Self {
+                let sum = *self as i128 + operand as i128;
+                sum.max(Self::MIN as i128).min(Self::MAX as i128) as Self
+            }
+        }
+    )*};
+}
+
+narrow_accumulator!(i32, i64);
+
+/// `Memory`'s own accumulator.
+impl Accumulator for isize {
+    fn checked_add(&self, operand: isize) -> Option<Self> {
+        isize::checked_add(*self, operand)
+    }
+    fn wrapping_add(&self, operand: isize) -> Self {
+        isize::wrapping_add(*self, operand)
+    }
+    fn saturating_add(&self, operand: isize) -> Self {
+        isize::saturating_add(*self, operand)
+    }
+}
+
+impl Accumulator for i128 {
+    fn checked_add(&self, operand: isize) -> Option<Self> {
+        i128::checked_add(*self, operand as i128)
+    }
+    fn wrapping_add(&self, operand: isize) -> Self {
+        i128::wrapping_add(*self, operand as i128)
+    }
+    fn saturating_add(&self, operand: isize) -> Self {
+        i128::saturating_add(*self, operand as i128)
+    }
+}
+
+/// Arbitrary precision, so every mode is the same.
+impl Accumulator for BigInt {
+    fn checked_add(&self, operand: isize) -> Option<Self> {
+        Some(self + operand)
+    }
+    fn wrapping_add(&self, operand: isize) -> Self {
+        self + operand
+    }
+    fn saturating_add(&self, operand: isize) -> Self {
+        self + operand
+    }
+}
+
+/// The accumulator and index after executing the instruction at `index`, or the kind of fault
+/// it would be. This is how `Memory` and `Vm` both decide what an instruction does.
+pub(crate) fn lookahead<A: Accumulator>(
+    instructions: &[Instruction],
+    index: isize,
+    accumulator: &A,
+    inputs: &VecDeque<isize>,
+    mode: Mode,
+) -> Result<(A, isize), FaultKind> {
+    let instruction = match index {
+        i if i < 0 => return Err(FaultKind::NegativeIndex),
+        i => instructions.get(i as usize).ok_or(FaultKind::PastEnd)?,
+    };
+    let (accumulator, offset) = match *instruction {
+        Instruction::Accumulate(a) => (accumulator.add(a, mode), 1),
+        Instruction::Jump(j) => (Some(accumulator.clone()), j),
+        Instruction::Input(_) => match inputs.front() {
+            Some(value) => (A::default().add(*value, mode), 1),
+            None => return Err(FaultKind::NeedsInput),
+        },
+        Instruction::Noop(_) | Instruction::Output(_) => (Some(accumulator.clone()), 1),
+    };
+    match (accumulator, index.checked_add(offset)) {
+        (Some(_), Some(i)) if i < 0 => Err(FaultKind::NegativeIndex),
+        (Some(_), Some(i)) if i as usize > instructions.len() => Err(FaultKind::PastEnd),
+        (Some(accumulator), Some(i)) => Ok((accumulator, i)),
+        _ => Err(FaultKind::Overflow),
+    }
+}
+
+impl From<FaultKind> for Fault {
+    fn from(kind: FaultKind) -> Self {
+        match kind {
+            FaultKind::NegativeIndex => Fault::NegativeIndex,
+            FaultKind::PastEnd => Fault::PastEnd,
+            FaultKind::Overflow => Fault::Overflow,
+            FaultKind::NeedsInput => Fault::NeedsInput,
+        }
+    }
+}
+
+/// Runs `Memory`'s instructions, deciding what each does the same way, with a choice of
+/// accumulator and overflow behaviour.
+/// An `out` doesn't pause the run as it does for `Memory`, but adds to `outputs`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Vm<A> {
+    pub instructions: Vec<Instruction>,
+    pub accumulator: A,
+    pub index: isize,
+    pub inputs: VecDeque<isize>,
+    /// Everything written by `out`, oldest first
+    pub outputs: Vec<A>,
+    pub mode: Mode,
+}
+
+impl<A: Accumulator> Vm<A> {
+    pub fn new<I>(instructions: I, mode: Mode) -> Self
+    where
+        I: IntoIterator<Item = Instruction>,
+    {
+        Self {
+            instructions: instructions.into_iter().collect(),
+            accumulator: A::default(),
+            index: 0,
+            inputs: VecDeque::new(),
+            outputs: Vec::new(),
+            mode,
+        }
+    }
+    /// `None` if `memory`'s accumulator doesn't fit and `mode` is `Checked`.
+    pub fn from_memory(memory: &Memory, mode: Mode) -> Option<Self> {
+        Some(Self {
+            instructions: memory.instructions.clone(),
+            accumulator: A::default().add(memory.accumulator, mode)?,
+            index: memory.index,
+            inputs: memory.inputs.clone(),
+            outputs: Vec::new(),
+            mode,
+        })
+    }
+    pub fn is_halted(&self) -> bool {
+        self.index >= 0 && self.index as usize == self.instructions.len()
+    }
+    /// Execute the instruction at `index`. On a fault, nothing changes.
+    pub fn step(&mut self) -> Result<(), Fault> {
+        let (accumulator, index) = lookahead(
+            &self.instructions,
+            self.index,
+            &self.accumulator,
+            &self.inputs,
+            self.mode,
+        )?;
+        match self.instructions[self.index as usize] {
+            Instruction::Input(_) => {
+                self.inputs.pop_front();
+            }
+            Instruction::Output(_) => self.outputs.push(accumulator.clone()),
+            _ => (),
+        }
+        self.accumulator = accumulator;
+        self.index = index;
+        Ok(())
+    }
+    /// Step until halted, or `max_steps` have been taken.
+    /// Returns whether the machine halted.
+    pub fn run(&mut self, max_steps: usize) -> Result<bool, Fault> {
+        for _ in 0..max_steps {
+            if self.is_halted() {
+                return Ok(true);
+            }
+            self.step()?;
+        }
+        Ok(self.is_halted())
+    }
+    /// Step until halted, or until the next instruction would be one already executed.
+    /// Returns whether the machine halted.
+    pub fn run_once(&mut self) -> Result<bool, Fault> {
+        let mut executed = HashSet::new();
+        while !self.is_halted() {
+            if !executed.insert(self.index) {
+                return Ok(false);
+            }
+            self.step()?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        steps::{Progress, State},
+        Status,
+    };
+    use proptest::prelude::*;
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n";
+
+    fn vm<A: Accumulator>(source: &str, mode: Mode) -> Vm<A> {
+        Vm::from_memory(&source.parse().unwrap(), mode).unwrap()
+    }
+
+    #[test]
+    fn example() {
+        let mut v: Vm<i32> = vm(EXAMPLE, Mode::Checked);
+        assert_eq!(v.run_once(), Ok(false));
+        assert_eq!(v.accumulator, 5);
+        let mut v: Vm<BigInt> = vm(EXAMPLE, Mode::Checked);
+        assert_eq!(v.run_once(), Ok(false));
+        assert_eq!(v.accumulator, BigInt::from(5));
+    }
+    #[test]
+    fn modes() {
+        let source = "acc +2147483647\nacc +1\nacc -1\n";
+        let mut checked: Vm<i32> = vm(source, Mode::Checked);
+        assert_eq!(checked.run(10), Err(Fault::Overflow));
+        assert_eq!((checked.index, checked.accumulator), (1, i32::MAX));
+        let mut wrapping: Vm<i32> = vm(source, Mode::Wrapping);
+        assert_eq!(wrapping.run(10), Ok(true));
+        assert_eq!(wrapping.accumulator, i32::MAX);
+        let mut saturating: Vm<i32> = vm(source, Mode::Saturating);
+        assert_eq!(saturating.run(10), Ok(true));
+        assert_eq!(saturating.accumulator, i32::MAX - 1);
+        let mut wider: Vm<i64> = vm(source, Mode::Checked);
+        assert_eq!(wider.run(10), Ok(true));
+        assert_eq!(wider.accumulator, i32::MAX as i64);
+    }
+    #[test]
+    fn operands_wider_than_the_accumulator() {
+        let source = "acc +4294967296\nacc -4294967295\n";
+        let mut checked: Vm<i32> = vm(source, Mode::Checked);
+        assert_eq!(checked.run(10), Err(Fault::Overflow));
+        let mut wrapping: Vm<i32> = vm(source, Mode::Wrapping);
+        assert_eq!(wrapping.run(10), Ok(true));
+        assert_eq!(wrapping.accumulator, 1);
+        let mut saturating: Vm<i32> = vm(source, Mode::Saturating);
+        assert_eq!(saturating.run(10), Ok(true));
+        assert_eq!(saturating.accumulator, i32::MIN);
+    }
+    #[test]
+    fn never_overflows() {
+        let source = "acc +9223372036854775807\njmp -1\n";
+        let mut big: Vm<BigInt> = vm(source, Mode::Checked);
+        assert_eq!(big.run(200), Ok(false));
+        assert_eq!(big.accumulator, BigInt::from(i64::MAX) * 100);
+        let mut wide: Vm<i128> = vm(source, Mode::Checked);
+        assert_eq!(wide.run(200), Ok(false));
+        let mut narrow: Vm<i64> = vm(source, Mode::Saturating);
+        assert_eq!(narrow.run(200), Ok(false));
+        assert_eq!(narrow.accumulator, i64::MAX);
+        let mut narrow: Vm<i64> = vm(source, Mode::Checked);
+        assert_eq!(narrow.run(200), Err(Fault::Overflow));
+        assert_eq!(narrow.index, 0);
+    }
+    #[test]
+    fn inputs_are_converted() {
+        let mut memory: Memory = "in +0\n".parse().unwrap();
+        memory.inputs.push_back(-1 << 40);
+        let mut checked = Vm::<i32>::from_memory(&memory, Mode::Checked).unwrap();
+        assert_eq!(checked.step(), Err(Fault::Overflow));
+        assert_eq!(checked.inputs.len(), 1);
+        let mut saturating = Vm::<i32>::from_memory(&memory, Mode::Saturating).unwrap();
+        assert_eq!(saturating.run(1), Ok(true));
+        assert_eq!(saturating.accumulator, i32::MIN);
+        let mut empty = Vm::<i32>::new(memory.instructions, Mode::Checked);
+        assert_eq!(empty.step(), Err(Fault::NeedsInput));
+    }
+    #[test]
+    fn outputs() {
+        let mut v: Vm<BigInt> = vm("acc +2\nout +0\nacc -3\nout +7\n", Mode::Checked);
+        assert_eq!(v.run(10), Ok(true));
+        assert_eq!(v.outputs, vec![BigInt::from(2), BigInt::from(-1)]);
+    }
+
+    proptest! {
+        #[test]
+        fn agrees_with_memory(memory in any::<Memory>()) {
+            let mut v: Vm<i64> = Vm::from_memory(&memory, Mode::Checked).unwrap();
+            let mut outputs = Vec::new();
+            for progress in memory.steps().skip(1).take(200) {
+                match progress {
+                    Progress::At(State { index, accumulator, output, .. }) => {
+                        prop_assert_eq!(v.step(), Ok(()));
+                        prop_assert_eq!((v.index, v.accumulator), (index, accumulator as i64));
+                        outputs.extend(output.map(|o| o as i64));
+                    }
+                    Progress::Stopped(Status::Halted(_)) => prop_assert!(v.is_halted()),
+                    Progress::Stopped(Status::AwaitingInput(_)) => {
+                        prop_assert_eq!(v.step(), Err(Fault::NeedsInput));
+                    }
+                    Progress::Stopped(Status::Faulted(fault)) => {
+                        prop_assert_eq!(v.step(), Err(fault.kind().into()));
+                    }
+                    Progress::Stopped(status) => panic!("{:?}", status),
+                }
+            }
+            prop_assert_eq!(v.outputs, outputs);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{words, Instruction, Memory, ParseInstructionError, ParseMemoryError, ParseReason};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Assemble a program which, on top of what `Memory::from_str` accepts, may contain:
+/// - `label:` definitions, on their own line or before an instruction
+/// - label names in place of an offset, e.g `jmp start`
+/// - `;` comments
+/// - blank lines
+///
+/// A label at the very end of the program refers to the halt point.
+/// Like `Memory::from_str`, every bad line is reported.
+pub fn assemble(source: &str) -> Result<Memory, ParseMemoryError> {
+    let mut labels = HashMap::new();
+    let mut errors = Vec::new();
+    // Line number, words and length of each line with an instruction on it
+    let mut lines = Vec::new();
+
+    // First pass: find out where every label points
+    for (number, line) in source.lines().enumerate() {
+        let code = match line.find(';') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        let mut words = words(code);
+        while let Some(name) = words.first().and_then(|(_, word)| word.strip_suffix(':')) {
+            let (columns, _) = words.remove(0);
+            let reason = if !is_label(name) {
+                ParseReason::BadLabel(name.to_string())
+            } else if labels.contains_key(name) {
+                ParseReason::DuplicateLabel(name.to_string())
+            } else {
+                labels.insert(name, lines.len());
+                continue;
+            };
+            errors.push(ParseInstructionError {
+                line: number + 1,
+                columns,
+                reason,
+            })
+        }
+        if !words.is_empty() {
+            lines.push((number + 1, words, code.len()))
+        }
+    }
+
+    // Second pass: resolve labels into relative offsets
+    let mut instructions = Vec::new();
+    for (index, (line, words, end)) in lines.iter().enumerate() {
+        let operand = |word: &str| match word.parse::<isize>() {
+            Ok(offset) => Ok(offset),
+            Err(e) => match labels.get(word) {
+                Some(target) => Ok(*target as isize - index as isize),
+                None if is_label(word) => Err(ParseReason::UnknownLabel(word.to_string())),
+                None => Err(ParseReason::BadInteger(e)),
+            },
+        };
+        match Instruction::from_words(words, *end, operand) {
+            Ok(instruction) => instructions.push(instruction),
+            Err(mut e) => {
+                e.line = *line;
+                errors.push(e)
+            }
+        }
+    }
+
+    errors.sort_by_key(|e| e.line);
+    match errors.is_empty() {
+        true => Ok(Memory::new(instructions)),
+        false => Err(ParseMemoryError { errors }),
+    }
+}
+
+/// Where a `jmp` lands, if that's inside the program or on the halt point.
+fn jump_target(index: usize, instruction: &Instruction, len: usize) -> Option<usize> {
+    match *instruction {
+        Instruction::Jump(j) => match (index as isize).checked_add(j) {
+            Some(t) if t >= 0 && t as usize <= len => Some(t as usize),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Render `memory` as assembly, inventing a label for every `jmp` target.
+/// Jumps which leave the program keep their numeric offset.
+/// `assemble` turns the result back into the same instructions.
+pub fn disassemble(memory: &Memory) -> String {
+    let len = memory.instructions.len();
+    let targets: BTreeSet<usize> = memory
+        .instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| jump_target(index, instruction, len))
+        .collect();
+    // Number the labels in program order
+    let labels: BTreeMap<usize, String> = targets
+        .into_iter()
+        .enumerate()
+        .map(|(n, target)| (target, format!("l{}", n)))
+        .collect();
+
+    let mut source = String::new();
+    for index in 0..=len {
+        if let Some(label) = labels.get(&index) {
+            source.push_str(&format!("{}:\n", label));
+        }
+        if let Some(instruction) = memory.instructions.get(index) {
+            match jump_target(index, instruction, len) {
+                Some(target) => source.push_str(&format!("    jmp {}\n", labels[&target])),
+                None => source.push_str(&format!("    {}\n", instruction)),
+            }
+        }
+    }
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use Instruction::*;
+
+    #[test]
+    fn labels_and_comments() {
+        let source = "
+; The example from day 8
+start:
+    nop +0      ; does nothing
+again:
+    acc +1
+    jmp skip
+back: acc +3
+    jmp again
+
+    acc -99
+skip:
+skip_too:
+    acc +1
+    jmp back
+    acc +6
+";
+        assert_eq!(
+            assemble(source).unwrap(),
+            "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n"
+                .parse()
+                .unwrap()
+        );
+    }
+    #[test]
+    fn label_at_end_halts() {
+        assert_eq!(
+            assemble("jmp end\nacc +1\nend:\n").unwrap().instructions,
+            vec![Jump(2), Accumulate(1)]
+        );
+    }
+    #[test]
+    fn label_errors() {
+        let e = assemble("a: nop +0\na: jmp b\n1a: acc +1\njmp +x\n").unwrap_err();
+        let reasons: Vec<(usize, ParseReason)> =
+            e.errors.into_iter().map(|e| (e.line, e.reason)).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (2, ParseReason::DuplicateLabel(String::from("a"))),
+                (2, ParseReason::UnknownLabel(String::from("b"))),
+                (3, ParseReason::BadLabel(String::from("1a"))),
+                (
+                    4,
+                    ParseReason::BadInteger("+x".parse::<isize>().unwrap_err())
+                ),
+            ]
+        );
+    }
+    #[test]
+    fn disassemble_example() {
+        let memory: Memory =
+            "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\njmp -9\n"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            disassemble(&memory),
+            "    nop +0
+l0:
+    acc +1
+    jmp l2
+l1:
+    acc +3
+    jmp l0
+    acc -99
+l2:
+    acc +1
+    jmp l1
+    jmp -9
+"
+        );
+    }
+
+    fn instruction() -> impl Strategy<Value = Instruction> {
+        prop_oneof![
+            (-10..10isize).prop_map(Noop),
+            (-10..10isize).prop_map(Accumulate),
+            (-10..10isize).prop_map(Jump),
+            (-10..10isize).prop_map(Input),
+            (-10..10isize).prop_map(Instruction::Output),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn disassemble_then_assemble(instructions in prop::collection::vec(instruction(), 0..32)) {
+            let memory = Memory::new(instructions);
+            prop_assert_eq!(assemble(&disassemble(&memory)), Ok(memory));
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use aocompute::{debugger::Debugger, Memory};
+use std::{
+    env, error,
+    io::{self, BufRead, Write},
+    process,
+};
+
+fn main() -> Result<(), Box<dyn error::Error>> {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: aocompute-dbg <program>");
+            process::exit(1)
+        }
+    };
+    let mut debugger = Debugger::new(Memory::from_file(&path)?);
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    debugger.print(&mut out)?;
+    let mut lines = stdin.lock().lines();
+    loop {
+        write!(out, "(dbg) ")?;
+        out.flush()?;
+        match lines.next() {
+            Some(line) => {
+                if !debugger.execute(&line?, &mut out)? {
+                    return Ok(());
+                }
+            }
+            None => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use crate::{
+    immutable::ImmutableMemory,
+    varint::{self, VarintError},
+    Instruction, Memory,
+};
+use std::{error, fmt};
+
+const MAGIC: &[u8] = b"AOBC";
+const IMMUTABLE_MAGIC: &[u8] = b"AOIM";
+const VERSION: u8 = 1;
+
+/// Decodes values from the front of a byte slice, remembering how far it has got for errors.
+pub(crate) struct Reader<'b> {
+    bytes: &'b [u8],
+    len: usize,
+}
+
+impl<'b> Reader<'b> {
+    pub(crate) fn new(bytes: &'b [u8]) -> Self {
+        Self {
+            bytes,
+            len: bytes.len(),
+        }
+    }
+    pub(crate) fn offset(&self) -> usize {
+        self.len - self.bytes.len()
+    }
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+    pub(crate) fn byte(&mut self) -> Result<u8, BytecodeError> {
+        let offset = self.offset();
+        let (&byte, rest) = self
+            .bytes
+            .split_first()
+            .ok_or(BytecodeError::Truncated { offset })?;
+        self.bytes = rest;
+        Ok(byte)
+    }
+    pub(crate) fn header(&mut self, magic: &[u8], version: u8) -> Result<(), BytecodeError> {
+        if !self.bytes.starts_with(magic) {
+            return Err(BytecodeError::BadMagic);
+        }
+        self.bytes = &self.bytes[magic.len()..];
+        match self.byte()? {
+            v if v == version => Ok(()),
+            v => Err(BytecodeError::UnsupportedVersion(v)),
+        }
+    }
+    pub(crate) fn signed(&mut self) -> Result<isize, BytecodeError> {
+        let offset = self.offset();
+        match varint::read_signed(&mut self.bytes) {
+            Ok(v) if v >= isize::MIN as i64 && v <= isize::MAX as i64 => Ok(v as isize),
+            Ok(_) | Err(VarintError::TooLong) => Err(BytecodeError::Overflow { offset }),
+            Err(VarintError::Truncated) => Err(BytecodeError::Truncated { offset }),
+        }
+    }
+    /// A count of things which each take at least `size` bytes, so that a corrupted count
+    /// fails here rather than in a huge allocation.
+    pub(crate) fn length(&mut self, size: usize) -> Result<usize, BytecodeError> {
+        let offset = self.offset();
+        match varint::read_unsigned(&mut self.bytes) {
+            Ok(n) if n <= (self.bytes.len() / size) as u64 => Ok(n as usize),
+            Ok(_) | Err(VarintError::Truncated) => Err(BytecodeError::Truncated { offset }),
+            Err(VarintError::TooLong) => Err(BytecodeError::Overflow { offset }),
+        }
+    }
+    pub(crate) fn instructions(&mut self) -> Result<Vec<Instruction>, BytecodeError> {
+        // An opcode and an operand
+        let count = self.length(2)?;
+        let mut instructions = Vec::with_capacity(count);
+        for _ in 0..count {
+            let offset = self.offset();
+            let instruction: fn(isize) -> Instruction = match self.byte()? {
+                0 => Instruction::Noop,
+                1 => Instruction::Accumulate,
+                2 => Instruction::Jump,
+                3 => Instruction::Input,
+                4 => Instruction::Output,
+                opcode => return Err(BytecodeError::BadOpcode { offset, opcode }),
+            };
+            instructions.push(instruction(self.signed()?));
+        }
+        Ok(instructions)
+    }
+}
+
+pub(crate) fn write_instructions(bytes: &mut Vec<u8>, instructions: &[Instruction]) {
+    varint::write_unsigned(bytes, instructions.len() as u64);
+    for instruction in instructions.iter() {
+        bytes.push(match instruction {
+            Instruction::Noop(_) => 0,
+            Instruction::Accumulate(_) => 1,
+            Instruction::Jump(_) => 2,
+            Instruction::Input(_) => 3,
+            Instruction::Output(_) => 4,
+        });
+        varint::write_signed(bytes, instruction.operand() as i64);
+    }
+}
+
+pub(crate) fn write_memory(bytes: &mut Vec<u8>, memory: &Memory) {
+    varint::write_signed(bytes, memory.accumulator as i64);
+    varint::write_signed(bytes, memory.index as i64);
+    write_instructions(bytes, &memory.instructions);
+    varint::write_unsigned(bytes, memory.inputs.len() as u64);
+    for input in memory.inputs.iter() {
+        varint::write_signed(bytes, *input as i64);
+    }
+}
+
+pub(crate) fn read_memory(reader: &mut Reader) -> Result<Memory, BytecodeError> {
+    let accumulator = reader.signed()?;
+    let index = reader.signed()?;
+    let instructions = reader.instructions()?;
+    let inputs = (0..reader.length(1)?)
+        .map(|_| reader.signed())
+        .collect::<Result<_, _>>()?;
+    Ok(Memory {
+        instructions,
+        accumulator,
+        index,
+        inputs,
+    })
+}
+
+fn finish<T>(reader: Reader, value: T) -> Result<T, BytecodeError> {
+    match reader.is_empty() {
+        true => Ok(value),
+        false => Err(BytecodeError::TrailingBytes {
+            offset: reader.offset(),
+        }),
+    }
+}
+
+impl Memory {
+    /// A compact binary encoding of the whole state:
+    /// magic, version, accumulator, index, instructions, then queued inputs.
+    /// Instructions are an opcode byte and an operand, and integers are varints.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        write_memory(&mut bytes, self);
+        bytes
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        let mut reader = Reader::new(bytes);
+        reader.header(MAGIC, VERSION)?;
+        let memory = read_memory(&mut reader)?;
+        finish(reader, memory)
+    }
+}
+
+impl ImmutableMemory {
+    /// As `Memory::to_bytes`, with a different magic, followed by the visited indices.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = IMMUTABLE_MAGIC.to_vec();
+        bytes.push(VERSION);
+        write_memory(&mut bytes, &self.memory);
+        let mut visited: Vec<isize> = self.visited_indices.iter().copied().collect();
+        visited.sort_unstable();
+        varint::write_unsigned(&mut bytes, visited.len() as u64);
+        for index in visited {
+            varint::write_signed(&mut bytes, index as i64);
+        }
+        bytes
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BytecodeError> {
+        let mut reader = Reader::new(bytes);
+        reader.header(IMMUTABLE_MAGIC, VERSION)?;
+        let memory = read_memory(&mut reader)?;
+        let visited_indices = (0..reader.length(1)?)
+            .map(|_| reader.signed())
+            .collect::<Result<_, _>>()?;
+        finish(
+            reader,
+            Self {
+                memory,
+                visited_indices,
+            },
+        )
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum BytecodeError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated {
+        offset: usize,
+    },
+    /// A value too large for this platform's `isize`, or a varint too long for any 64-bit value
+    Overflow {
+        offset: usize,
+    },
+    BadOpcode {
+        offset: usize,
+        opcode: u8,
+    },
+    TrailingBytes {
+        offset: usize,
+    },
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::BadMagic => write!(f, "Not aocompute bytecode"),
+            BytecodeError::UnsupportedVersion(v) => write!(f, "Unsupported bytecode version {}", v),
+            BytecodeError::Truncated { offset } => {
+                write!(f, "Bytecode ends unexpectedly, reading byte {}", offset)
+            }
+            BytecodeError::Overflow { offset } => {
+                write!(f, "Value at byte {} is too large for this platform", offset)
+            }
+            BytecodeError::BadOpcode { offset, opcode } => {
+                write!(f, "Unknown opcode {} at byte {}", opcode, offset)
+            }
+            BytecodeError::TrailingBytes { offset } => {
+                write!(f, "Unexpected bytes after the end, from byte {}", offset)
+            }
+        }
+    }
+}
+
+impl error::Error for BytecodeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n";
+
+    #[test]
+    fn example() {
+        let mut memory: Memory = EXAMPLE.parse().unwrap();
+        memory.inputs = vec![5, -5].into();
+        let bytes = memory.to_bytes();
+        assert_eq!(&bytes[..7], b"AOBC\x01\x00\x00");
+        // `acc -99` needs two bytes for its operand
+        assert_eq!(bytes.len(), 7 + 1 + 9 * 2 + 1 + 1 + 2);
+        assert_eq!(Memory::from_bytes(&bytes), Ok(memory));
+    }
+    #[test]
+    fn corrupted() {
+        let bytes = EXAMPLE.parse::<Memory>().unwrap().to_bytes();
+        assert_eq!(Memory::from_bytes(b"AOTR"), Err(BytecodeError::BadMagic));
+        assert_eq!(
+            Memory::from_bytes(b"AOBC\x02"),
+            Err(BytecodeError::UnsupportedVersion(2))
+        );
+        for len in 0..bytes.len() {
+            assert!(Memory::from_bytes(&bytes[..len]).is_err());
+        }
+        // Without the count of inputs
+        assert_eq!(
+            Memory::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BytecodeError::Truncated { offset: 27 })
+        );
+        // Too short for the instruction count
+        assert_eq!(
+            Memory::from_bytes(&bytes[..bytes.len() - 3]),
+            Err(BytecodeError::Truncated { offset: 7 })
+        );
+        let mut bad = bytes.clone();
+        bad[10] = 9;
+        assert_eq!(
+            Memory::from_bytes(&bad),
+            Err(BytecodeError::BadOpcode {
+                offset: 10,
+                opcode: 9
+            })
+        );
+        // A huge instruction count
+        let mut huge = bytes[..7].to_vec();
+        huge.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert_eq!(
+            Memory::from_bytes(&huge),
+            Err(BytecodeError::Truncated { offset: 7 })
+        );
+        // An instruction count longer than any 64-bit value
+        let mut long = bytes[..7].to_vec();
+        long.extend_from_slice(&[0xff; 10]);
+        long.extend_from_slice(&bytes[8..]);
+        assert_eq!(
+            Memory::from_bytes(&long),
+            Err(BytecodeError::Overflow { offset: 7 })
+        );
+        let mut extra = bytes;
+        extra.push(0);
+        assert_eq!(
+            Memory::from_bytes(&extra).unwrap_err().to_string(),
+            "Unexpected bytes after the end, from byte 28"
+        );
+    }
+
+    #[test]
+    fn immutable() {
+        let immutable_memory: ImmutableMemory = EXAMPLE.parse().unwrap();
+        let stopped = match immutable_memory.run() {
+            crate::immutable::ImmutableStatus::WouldRevisit(m) => m,
+            _ => panic!(),
+        };
+        let bytes = stopped.to_bytes();
+        assert_eq!(&bytes[..4], b"AOIM");
+        assert_eq!(ImmutableMemory::from_bytes(&bytes), Ok(stopped));
+        assert_eq!(Memory::from_bytes(&bytes), Err(BytecodeError::BadMagic));
+    }
+
+    fn instruction() -> impl Strategy<Value = Instruction> {
+        prop_oneof![
+            any::<isize>().prop_map(Instruction::Noop),
+            any::<isize>().prop_map(Instruction::Accumulate),
+            any::<isize>().prop_map(Instruction::Jump),
+            any::<isize>().prop_map(Instruction::Input),
+            any::<isize>().prop_map(Instruction::Output),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(
+            instructions in prop::collection::vec(instruction(), 0..64),
+            accumulator in any::<isize>(),
+            index in any::<isize>(),
+            inputs in prop::collection::vec(any::<isize>(), 0..4),
+        ) {
+            let memory = Memory { instructions, accumulator, index, inputs: inputs.into() };
+            prop_assert_eq!(Memory::from_bytes(&memory.to_bytes()), Ok(memory));
+        }
+        #[test]
+        fn never_panics(bytes in prop::collection::vec(any::<u8>(), 0..64)) {
+            let mut with_header = b"AOBC\x01".to_vec();
+            with_header.extend(bytes.iter());
+            let _ = Memory::from_bytes(&bytes);
+            let _ = Memory::from_bytes(&with_header);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use crate::{Instruction, Memory};
+use petgraph::{
+    algo::kosaraju_scc,
+    dot::Dot,
+    graph::{DiGraph, NodeIndex},
+    visit::Dfs,
+};
+use std::{collections::BTreeSet, fmt, fs, io, path};
+
+/// A node in the control flow graph.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Node {
+    /// Instructions which always run one after the other, starting at `start`
+    Block {
+        start: usize,
+        instructions: Vec<Instruction>,
+    },
+    /// Just past the last instruction
+    Halt,
+    /// Anywhere else - executing a jump here faults
+    OutOfBounds,
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Node::Block {
+                start,
+                instructions,
+            } => {
+                for (offset, instruction) in instructions.iter().enumerate() {
+                    writeln!(f, "{}: {}", start + offset, instruction)?;
+                }
+                Ok(())
+            }
+            Node::Halt => write!(f, "halt"),
+            Node::OutOfBounds => write!(f, "out of bounds"),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Edge {
+    Fallthrough,
+    Jump,
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Edge::Fallthrough => Ok(()),
+            Edge::Jump => write!(f, "jmp"),
+        }
+    }
+}
+
+/// The basic blocks of a program, and how control passes between them.
+/// Built without running anything, so the accumulator and inputs play no part.
+/// Every instruction has exactly one successor, so every block has exactly one outgoing edge.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub graph: DiGraph<Node, Edge>,
+    /// The block containing `memory.index`, or `Halt`/`OutOfBounds` if it's not in the program
+    pub entry: NodeIndex,
+    pub halt: NodeIndex,
+    pub out_of_bounds: NodeIndex,
+    /// The block each instruction belongs to
+    blocks: Vec<NodeIndex>,
+}
+
+/// Where a jump at `index` lands, if it stays within the program (or halts).
+fn target(index: usize, offset: isize, len: usize) -> Option<usize> {
+    match (index as isize).checked_add(offset) {
+        Some(t) if t >= 0 && t as usize <= len => Some(t as usize),
+        _ => None,
+    }
+}
+
+impl Cfg {
+    pub fn new(memory: &Memory) -> Self {
+        let instructions = &memory.instructions;
+        let len = instructions.len();
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        if memory.index >= 0 {
+            leaders.insert(memory.index as usize);
+        }
+        for (index, instruction) in instructions.iter().enumerate() {
+            if let Instruction::Jump(j) = *instruction {
+                leaders.insert(index + 1);
+                if let Some(t) = target(index, j, len) {
+                    leaders.insert(t);
+                }
+            }
+        }
+
+        let mut graph = DiGraph::new();
+        let mut blocks = Vec::with_capacity(len);
+        for (index, instruction) in instructions.iter().enumerate() {
+            match leaders.contains(&index) {
+                true => blocks.push(graph.add_node(Node::Block {
+                    start: index,
+                    instructions: vec![instruction.clone()],
+                })),
+                false => {
+                    let block = *blocks.last().expect("0 is always a leader");
+                    if let Node::Block { instructions, .. } = &mut graph[block] {
+                        instructions.push(instruction.clone())
+                    }
+                    blocks.push(block)
+                }
+            }
+        }
+        let halt = graph.add_node(Node::Halt);
+        let out_of_bounds = graph.add_node(Node::OutOfBounds);
+        let node = |index: Option<usize>| match index {
+            Some(i) if i == len => halt,
+            Some(i) => blocks[i],
+            None => out_of_bounds,
+        };
+
+        // Only the last instruction of a block can leave it
+        for (index, instruction) in instructions.iter().enumerate() {
+            if index + 1 != len && blocks[index + 1] == blocks[index] {
+                continue;
+            }
+            let (to, edge) = match *instruction {
+                Instruction::Jump(j) => (node(target(index, j, len)), Edge::Jump),
+                _ => (node(Some(index + 1)), Edge::Fallthrough),
+            };
+            graph.add_edge(blocks[index], to, edge);
+        }
+
+        let entry = match memory.index {
+            i if i >= 0 && i as usize <= len => node(Some(i as usize)),
+            _ => out_of_bounds,
+        };
+        Self {
+            graph,
+            entry,
+            halt,
+            out_of_bounds,
+            blocks,
+        }
+    }
+    pub fn block_of(&self, index: usize) -> Option<NodeIndex> {
+        self.blocks.get(index).copied()
+    }
+    fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.graph.node_count()];
+        let mut dfs = Dfs::new(&self.graph, self.entry);
+        while let Some(node) = dfs.next(&self.graph) {
+            reachable[node.index()] = true;
+        }
+        reachable
+    }
+    /// Whether execution from the entry point reaches the end of the program.
+    /// Ignores overflow, and assumes every `in` gets its input.
+    pub fn halts(&self) -> bool {
+        self.reachable()[self.halt.index()]
+    }
+    /// Instructions which can never run, starting from the entry point.
+    pub fn unreachable(&self) -> Vec<usize> {
+        let reachable = self.reachable();
+        (0..self.blocks.len())
+            .filter(|index| !reachable[self.blocks[*index].index()])
+            .collect()
+    }
+    /// Groups of instructions which, once entered, are never left.
+    /// Includes loops the entry point never reaches.
+    pub fn infinite_loops(&self) -> Vec<Vec<usize>> {
+        let components = kosaraju_scc(&self.graph);
+        let mut component_of = vec![0; self.graph.node_count()];
+        for (id, component) in components.iter().enumerate() {
+            for node in component.iter() {
+                component_of[node.index()] = id;
+            }
+        }
+        let trapped: Vec<bool> = components
+            .iter()
+            .enumerate()
+            .map(|(id, component)| {
+                let cyclic =
+                    component.len() > 1 || self.graph.contains_edge(component[0], component[0]);
+                cyclic
+                    && component.iter().all(|node| {
+                        self.graph
+                            .neighbors(*node)
+                            .all(|neighbour| component_of[neighbour.index()] == id)
+                    })
+            })
+            .collect();
+        let mut loops = vec![Vec::new(); components.len()];
+        for (index, block) in self.blocks.iter().enumerate() {
+            let id = component_of[block.index()];
+            if trapped[id] {
+                loops[id].push(index);
+            }
+        }
+        loops.retain(|instructions| !instructions.is_empty());
+        loops.sort();
+        loops
+    }
+    /// Jumps which land outside the program (the end doesn't count - that's a halt).
+    pub fn out_of_bounds_jumps(&self) -> Vec<usize> {
+        let len = self.blocks.len();
+        self.graph
+            .node_indices()
+            .map(|node| &self.graph[node])
+            .filter_map(|node| match node {
+                Node::Block {
+                    start,
+                    instructions,
+                } => match instructions.last() {
+                    Some(Instruction::Jump(j)) => {
+                        let index = start + instructions.len() - 1;
+                        match target(index, *j, len) {
+                            None => Some(index),
+                            Some(_) => None,
+                        }
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+    /// The graph in Graphviz's DOT language.
+    pub fn to_dot(&self) -> String {
+        format!("{}", Dot::new(&self.graph))
+    }
+    pub fn to_file(&self, filename: impl AsRef<path::Path>) -> Result<(), io::Error> {
+        fs::write(filename, self.to_dot())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n";
+
+    fn starts(cfg: &Cfg) -> Vec<usize> {
+        cfg.graph
+            .node_indices()
+            .map(|node| &cfg.graph[node])
+            .filter_map(|node| match node {
+                Node::Block { start, .. } => Some(*start),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn example() {
+        let cfg = Cfg::new(&EXAMPLE.parse().unwrap());
+        assert_eq!(starts(&cfg), vec![0, 1, 3, 5, 6, 8]);
+        assert!(!cfg.halts());
+        assert_eq!(cfg.unreachable(), vec![5, 8]);
+        assert_eq!(cfg.infinite_loops(), vec![vec![1, 2, 3, 4, 6, 7]]);
+        assert_eq!(cfg.out_of_bounds_jumps(), Vec::<usize>::new());
+        assert_eq!(cfg.block_of(7), cfg.block_of(6));
+    }
+    #[test]
+    fn repaired_example() {
+        let mut memory: Memory = EXAMPLE.parse().unwrap();
+        memory.instructions[7] = Instruction::Noop(-4);
+        let cfg = Cfg::new(&memory);
+        assert!(cfg.halts());
+        assert_eq!(cfg.unreachable(), vec![3, 4, 5]);
+        assert_eq!(cfg.infinite_loops(), Vec::<Vec<usize>>::new());
+    }
+    #[test]
+    fn out_of_bounds() {
+        let cfg = Cfg::new(&"acc +1\njmp +5\nnop +0\njmp -3\njmp +0\n".parse().unwrap());
+        assert_eq!(cfg.out_of_bounds_jumps(), vec![1]);
+        assert_eq!(cfg.unreachable(), vec![2, 3, 4]);
+        // A jump to itself is a loop, one which jumps out of bounds is not
+        assert_eq!(cfg.infinite_loops(), vec![vec![4]]);
+        assert!(!cfg.halts());
+
+        let mut memory: Memory = "nop +0\n".parse().unwrap();
+        memory.index = 3;
+        let cfg = Cfg::new(&memory);
+        assert_eq!(cfg.entry, cfg.out_of_bounds);
+        assert_eq!(cfg.unreachable(), vec![0]);
+    }
+    #[test]
+    fn dot() {
+        let cfg = Cfg::new(&"acc +1\nnop +0\njmp -2\n".parse().unwrap());
+        let dot = cfg.to_dot();
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains(r#"0 [ label = "0: acc +1\l1: nop +0\l2: jmp -2\l" ]"#));
+        assert!(dot.contains(r#"0 -> 0 [ label = "jmp" ]"#));
+    }
+}
@@ -0,0 +1,238 @@
+use crate::{
+    isa::{Cpu, Machine},
+    Memory,
+};
+use std::collections::{BTreeMap, VecDeque};
+
+/// What following a sequence of states found.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Search {
+    /// The state after `start` steps comes round again every `length` steps
+    Cycle { start: usize, length: usize },
+    /// There's no state after `steps` steps - the machine halted, faulted or needs input
+    Ends { steps: usize },
+    /// Neither, within the step budget
+    GaveUp,
+}
+
+/// Brent's algorithm over the states reached by repeatedly applying `next` to `start`.
+/// `next` returns `None` when there is no next state.
+/// Only whole states are compared, so this is right however the next state is decided.
+/// Applies `next` at most about `2 * max_steps` times.
+pub fn brent<S, F>(start: &S, mut next: F, max_steps: usize) -> Search
+where
+    S: Clone + Eq,
+    F: FnMut(&S) -> Option<S>,
+{
+    // Find the cycle length, moving the tortoise up to the hare at each power of two
+    let (mut power, mut length) = (1, 1);
+    let mut tortoise = start.clone();
+    let mut hare = match next(start) {
+        Some(state) => state,
+        None => return Search::Ends { steps: 0 },
+    };
+    let mut steps = 1;
+    while tortoise != hare {
+        if steps >= max_steps {
+            return Search::GaveUp;
+        }
+        if power == length {
+            tortoise = hare.clone();
+            power *= 2;
+            length = 0;
+        }
+        hare = match next(&hare) {
+            Some(state) => state,
+            None => return Search::Ends { steps },
+        };
+        length += 1;
+        steps += 1;
+    }
+
+    // Then the start, with the hare `length` ahead of the tortoise
+    let mut tortoise = start.clone();
+    let mut hare = start.clone();
+    for _ in 0..length {
+        hare = next(&hare).expect("Already been here");
+    }
+    let mut cycle_start = 0;
+    while tortoise != hare {
+        tortoise = next(&tortoise).expect("Already been here");
+        hare = next(&hare).expect("Already been here");
+        cycle_start += 1;
+    }
+    Search::Cycle {
+        start: cycle_start,
+        length,
+    }
+}
+
+/// Everything about a `Memory` which can change.
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct State {
+    accumulator: isize,
+    index: isize,
+    inputs: VecDeque<isize>,
+}
+
+impl Memory {
+    /// Look for a repeated state (index, accumulator and inputs) in the next `max_steps` steps.
+    /// Unlike `ImmutableMemory`, a repeated index alone isn't a cycle.
+    pub fn find_cycle(&self, max_steps: usize) -> Search {
+        // Step one `Memory`, so the instructions aren't copied for every state
+        let mut scratch = Some(self.clone());
+        let next = |state: &State| {
+            let mut memory = scratch.take()?;
+            memory.accumulator = state.accumulator;
+            memory.index = state.index;
+            memory.inputs.clone_from(&state.inputs);
+            let memory = memory.try_execute_instruction().ok()?;
+            let next = State {
+                accumulator: memory.accumulator,
+                index: memory.index,
+                inputs: memory.inputs.clone(),
+            };
+            scratch = Some(memory);
+            Some(next)
+        };
+        let start = State {
+            accumulator: self.accumulator,
+            index: self.index,
+            inputs: self.inputs.clone(),
+        };
+        brent(&start, next, max_steps)
+    }
+}
+
+/// Everything about a `Cpu` which decides what it does next - not `outputs`, which only grow.
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct CpuState {
+    accumulator: isize,
+    index: isize,
+    registers: Vec<isize>,
+    data: BTreeMap<isize, isize>,
+    inputs: VecDeque<isize>,
+}
+
+impl CpuState {
+    fn of(cpu: &Cpu) -> Self {
+        Self {
+            accumulator: cpu.accumulator,
+            index: cpu.index,
+            registers: cpu.registers.clone(),
+            data: cpu.data.clone(),
+            inputs: cpu.inputs.clone(),
+        }
+    }
+}
+
+impl Machine {
+    /// Look for a repeated state in the next `max_steps` steps: the `cpu`, including registers
+    /// and data, but not `outputs`.
+    pub fn find_cycle(&self, max_steps: usize) -> Search {
+        let mut machine = self.clone();
+        let next = |state: &CpuState| {
+            let cpu = &mut machine.cpu;
+            cpu.accumulator = state.accumulator;
+            cpu.index = state.index;
+            cpu.registers.clone_from(&state.registers);
+            cpu.data.clone_from(&state.data);
+            cpu.inputs.clone_from(&state.inputs);
+            cpu.outputs.clear();
+            machine.step().ok()?;
+            Some(CpuState::of(&machine.cpu))
+        };
+        brent(&CpuState::of(&self.cpu), next, max_steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::isa::Registry;
+
+    #[test]
+    fn sequences() {
+        // 0, 1, 2, 3, 4, 5, 6, 3, 4, ...
+        let next = |x: &u32| Some(if *x == 6 { 3 } else { x + 1 });
+        assert_eq!(
+            brent(&0, next, 100),
+            Search::Cycle {
+                start: 3,
+                length: 4
+            }
+        );
+        assert_eq!(brent(&0, next, 5), Search::GaveUp);
+        assert_eq!(
+            brent(&0, |x: &u32| x.checked_sub(1), 100),
+            Search::Ends { steps: 0 }
+        );
+        assert_eq!(
+            brent(&7, |x: &u32| x.checked_sub(1), 100),
+            Search::Ends { steps: 7 }
+        );
+    }
+    #[test]
+    fn memory() {
+        let memory: Memory = "nop +0\nacc +1\nacc -1\njmp -2\n".parse().unwrap();
+        assert_eq!(
+            memory.find_cycle(100),
+            Search::Cycle {
+                start: 1,
+                length: 3
+            }
+        );
+        let memory: Memory = "jmp +0\n".parse().unwrap();
+        assert_eq!(
+            memory.find_cycle(100),
+            Search::Cycle {
+                start: 0,
+                length: 1
+            }
+        );
+        // The index repeats, but the accumulator never does
+        let memory: Memory =
+            "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n"
+                .parse()
+                .unwrap();
+        assert_eq!(memory.find_cycle(1000), Search::GaveUp);
+        let memory: Memory = "acc +1\nin +0\n".parse().unwrap();
+        assert_eq!(memory.find_cycle(1000), Search::Ends { steps: 1 });
+    }
+    #[test]
+    fn registers() {
+        let registry = Registry::with_registers(&["a", "b"]);
+        // Revisits `add`, then counts down to 0 and halts
+        let countdown = registry
+            .parse("set a +3\nset b -1\nadd a b\njnz a -1\n")
+            .unwrap();
+        assert_eq!(countdown.find_cycle(100), Search::Ends { steps: 8 });
+        let forever = registry
+            .parse("set a +1\nset b -1\nadd acc a\nadd acc b\njmp -2\n")
+            .unwrap();
+        assert_eq!(
+            forever.find_cycle(100),
+            Search::Cycle {
+                start: 2,
+                length: 3
+            }
+        );
+        // Outputs pile up, but don't change what happens next
+        let writer = registry.parse("set a +1\nout +0\njmp -1\n").unwrap();
+        assert_eq!(
+            writer.find_cycle(100),
+            Search::Cycle {
+                start: 1,
+                length: 2
+            }
+        );
+        let writer = Registry::default().parse("out +0\njmp -1\n").unwrap();
+        assert_eq!(
+            writer.find_cycle(100),
+            Search::Cycle {
+                start: 0,
+                length: 2
+            }
+        );
+    }
+}
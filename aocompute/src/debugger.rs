@@ -0,0 +1,310 @@
+use crate::{
+    observer::{Breakpoints, Control, VmObserver},
+    FaultKind, Instruction, Memory,
+};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::mem;
+use std::time::{Duration, Instant};
+
+/// How many instructions either side of `index` to show.
+const CONTEXT: isize = 4;
+
+const HELP: &str = "\
+step [n]              execute n instructions (default 1)
+continue              run until a breakpoint, watch, halt, fault or revisit
+break <index>         stop before executing the instruction at index
+watch acc             stop whenever the accumulator changes
+print                 show the state and surrounding instructions
+input <value>         queue a value for `in` to read
+patch <index> <instr> replace an instruction, e.g `patch 7 nop -4`
+reset                 restart the (patched) program from the beginning
+timeout <seconds>     stop step and continue after this long (0 for never)
+quit                  leave the debugger";
+
+/// State behind the `aocompute-dbg` REPL.
+/// Like `ImmutableMemory`, it remembers which indices have been visited, but only to show them
+/// - nothing here stops execution on a revisit except `continue`.
+#[derive(Debug)]
+pub struct Debugger {
+    pub memory: Memory,
+    pub visited: HashSet<isize>,
+    pub breakpoints: Breakpoints,
+    pub watch_accumulator: bool,
+    /// How long `step` and `continue` may run for
+    pub timeout: Option<Duration>,
+}
+
+impl Debugger {
+    pub fn new(memory: Memory) -> Self {
+        Self {
+            memory,
+            visited: HashSet::new(),
+            breakpoints: Breakpoints::default(),
+            watch_accumulator: false,
+            timeout: Some(Duration::from_secs(5)),
+        }
+    }
+    /// Run one line of user input. Returns `false` when the user wants to quit.
+    pub fn execute<W: Write>(&mut self, command: &str, out: &mut W) -> io::Result<bool> {
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next()) {
+            (None, _) => (),
+            (Some("step"), None) => self.run(Some(1), out)?,
+            (Some("step"), Some(n)) => match n.parse() {
+                Ok(n) => self.run(Some(n), out)?,
+                Err(e) => writeln!(out, "Bad step count `{}`: {}", n, e)?,
+            },
+            (Some("continue"), None) => self.run(None, out)?,
+            (Some("break"), Some(index)) => match index.parse() {
+                Ok(index) => {
+                    self.breakpoints.indices.insert(index);
+                    writeln!(out, "Breakpoint at {}", index)?
+                }
+                Err(e) => writeln!(out, "Bad index `{}`: {}", index, e)?,
+            },
+            (Some("watch"), Some("acc")) => {
+                self.watch_accumulator = true;
+                writeln!(out, "Watching the accumulator")?
+            }
+            (Some("print"), None) => self.print(out)?,
+            (Some("input"), Some(value)) => match value.parse() {
+                Ok(value) => {
+                    self.memory.inputs.push_back(value);
+                    writeln!(out, "Queued input {}", value)?
+                }
+                Err(e) => writeln!(out, "Bad input `{}`: {}", value, e)?,
+            },
+            (Some("patch"), Some(index)) => {
+                let instruction = words.collect::<Vec<_>>().join(" ");
+                match (index.parse::<usize>(), instruction.parse::<Instruction>()) {
+                    (Ok(index), Ok(instruction)) if index < self.memory.instructions.len() => {
+                        self.memory.instructions[index] = instruction;
+                        self.print(out)?
+                    }
+                    (Ok(index), Ok(_)) => writeln!(out, "No instruction at {}", index)?,
+                    (Err(e), _) => writeln!(out, "Bad index `{}`: {}", index, e)?,
+                    (_, Err(e)) => writeln!(out, "Bad instruction `{}`: {}", instruction, e)?,
+                }
+            }
+            (Some("reset"), None) => {
+                self.memory = Memory::new(self.memory.instructions.clone());
+                self.visited.clear();
+                self.print(out)?
+            }
+            (Some("timeout"), Some(seconds)) => match seconds.parse() {
+                Ok(0) => {
+                    self.timeout = None;
+                    writeln!(out, "No timeout")?
+                }
+                Ok(seconds) => {
+                    self.timeout = Some(Duration::from_secs(seconds));
+                    writeln!(out, "Timeout is {}s", seconds)?
+                }
+                Err(e) => writeln!(out, "Bad timeout `{}`: {}", seconds, e)?,
+            },
+            (Some("quit"), None) => return Ok(false),
+            _ => writeln!(out, "{}", HELP)?,
+        }
+        Ok(true)
+    }
+    /// Step until `limit` instructions have run, or something worth stopping for happens.
+    /// With no limit, revisiting an instruction is worth stopping for.
+    fn run<W: Write>(&mut self, limit: Option<usize>, out: &mut W) -> io::Result<()> {
+        let start = Instant::now();
+        let mut steps = 0;
+        while limit != Some(steps) {
+            if let Some(timeout) = self.timeout {
+                if steps % 4096 == 0 && start.elapsed() >= timeout {
+                    writeln!(out, "Timed out after {}s", timeout.as_secs_f64())?;
+                    break;
+                }
+            }
+            let len = self.memory.instructions.len() as isize;
+            match (self.memory.index, self.memory.lookahead()) {
+                (index, _) if index == len => {
+                    writeln!(out, "Halted with accumulator {}", self.memory.accumulator)?;
+                    break;
+                }
+                (_, Err(FaultKind::NeedsInput)) => {
+                    writeln!(out, "Waiting for input")?;
+                    break;
+                }
+                (index, Err(_)) if index < 0 || index > len => {
+                    writeln!(out, "Invalid index {}", index)?;
+                    break;
+                }
+                (_, Err(kind)) => {
+                    writeln!(out, "{}", kind.at(self.memory.clone()))?;
+                    break;
+                }
+                (_, Ok(_)) => (),
+            }
+            let index = self.memory.index;
+            if self.breakpoints.before_step(&self.memory) == Control::Stop {
+                match steps {
+                    // Always let the first instruction run, so that `step` and `continue` get
+                    // off a breakpoint - asking again is how `Breakpoints` lets a run resume
+                    0 => {
+                        self.breakpoints.before_step(&self.memory);
+                    }
+                    _ => {
+                        writeln!(out, "Breakpoint at {}", index)?;
+                        break;
+                    }
+                }
+            }
+            if steps > 0 && limit.is_none() && self.visited.contains(&index) {
+                writeln!(out, "About to revisit {}", index)?;
+                break;
+            }
+            let accumulator = self.memory.accumulator;
+            let memory = mem::replace(&mut self.memory, Memory::new(Vec::new()));
+            self.memory = memory.execute_instruction();
+            if let Instruction::Output(_) = self.memory.instructions[index as usize] {
+                writeln!(out, "Output {}", self.memory.accumulator)?
+            }
+            self.visited.insert(index);
+            steps += 1;
+            if self.watch_accumulator && accumulator != self.memory.accumulator {
+                writeln!(
+                    out,
+                    "Accumulator changed from {} to {}",
+                    accumulator, self.memory.accumulator
+                )?;
+                break;
+            }
+        }
+        self.print(out)
+    }
+    /// Show the state, and the instructions around `index`.
+    /// `=>` marks the next instruction, `*` those already visited and `b` breakpoints.
+    pub fn print<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "index {}, accumulator {}",
+            self.memory.index, self.memory.accumulator
+        )?;
+        let len = self.memory.instructions.len() as isize;
+        let start = (self.memory.index - CONTEXT).max(0);
+        let end = (self.memory.index + CONTEXT + 1).min(len);
+        let mark = |marked: bool, mark| if marked { mark } else { " " };
+        for index in start..end {
+            writeln!(
+                out,
+                "{}{} {}{:>4} {} {}",
+                mark(index == self.memory.index, "="),
+                mark(index == self.memory.index, ">"),
+                mark(self.breakpoints.indices.contains(&index), "b"),
+                index,
+                mark(self.visited.contains(&index), "*"),
+                self.memory.instructions[index as usize]
+            )?;
+        }
+        if self.memory.index == len {
+            writeln!(out, "=>  {:>4}   (halt)", len)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(commands: &[&str]) -> (Debugger, String) {
+        let memory: Memory =
+            "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n"
+                .parse()
+                .unwrap();
+        let mut debugger = Debugger::new(memory);
+        let mut out = Vec::new();
+        for command in commands {
+            debugger.execute(command, &mut out).unwrap();
+        }
+        (debugger, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn step_and_print() {
+        let (debugger, out) = session(&["step 3"]);
+        assert_eq!(debugger.memory.index, 6);
+        assert_eq!(
+            out,
+            "index 6, accumulator 1
+       2 * jmp +4
+       3   acc +3
+       4   jmp -3
+       5   acc -99
+=>     6   acc +1
+       7   jmp -4
+       8   acc +6
+"
+        );
+    }
+    #[test]
+    fn continue_stops_on_revisit() {
+        let (debugger, out) = session(&["continue"]);
+        assert!(out.starts_with("About to revisit 1\n"));
+        assert_eq!(debugger.memory.accumulator, 5);
+    }
+    #[test]
+    fn breakpoints_and_watches() {
+        let (debugger, _) = session(&["break 4", "continue"]);
+        assert_eq!(debugger.memory.index, 4);
+        // Stepping gets off the breakpoint, and stops when it comes round again
+        let (debugger, out) = session(&["break 1", "continue", "step 10"]);
+        assert_eq!(out.matches("Breakpoint at 1\n").count(), 3);
+        assert_eq!((debugger.memory.index, debugger.memory.accumulator), (1, 5));
+        // Each step off a breakpoint on a one-instruction loop comes straight back to it
+        let (_, out) = session(&["patch 0 jmp +0", "break 0", "step 2", "step 2", "step 2"]);
+        assert_eq!(out.matches("Breakpoint at 0\n").count(), 4);
+        let (debugger, out) = session(&["watch acc", "continue", "continue"]);
+        assert_eq!(debugger.memory.index, 7);
+        assert!(out.contains("Accumulator changed from 1 to 2"));
+    }
+    #[test]
+    fn patch_and_reset() {
+        let (debugger, out) = session(&["continue", "patch 7 nop -4", "reset", "continue"]);
+        assert!(out.ends_with(
+            "Halted with accumulator 8
+index 9, accumulator 8
+       5   acc -99
+       6 * acc +1
+       7 * nop -4
+       8 * acc +6
+=>     9   (halt)
+"
+        ));
+        assert_eq!(debugger.memory.accumulator, 8);
+        let (_, out) = session(&["patch 9 nop +0", "patch 1 foo"]);
+        assert!(out.contains("No instruction at 9"));
+        assert!(out.contains("Bad instruction `foo`"));
+    }
+    #[test]
+    fn timeout() {
+        let (mut debugger, _) = session(&["patch 0 jmp +0", "timeout 0"]);
+        assert_eq!(debugger.timeout, None);
+        debugger.timeout = Some(Duration::from_millis(10));
+        let mut out = Vec::new();
+        debugger.execute("step 1000000000", &mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("Timed out after 0.01s\n"));
+    }
+    #[test]
+    fn input_and_output() {
+        let (debugger, out) = session(&[
+            "patch 0 in +0",
+            "patch 1 out +0",
+            "step 3",
+            "input 4",
+            "step 3",
+        ]);
+        assert!(out.contains("Waiting for input\n"));
+        assert!(out.contains("Output 4\n"));
+        assert_eq!(debugger.memory.index, 6);
+        let (_, out) = session(&["input x"]);
+        assert!(out.contains("Bad input `x`"));
+    }
+}
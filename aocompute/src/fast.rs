@@ -0,0 +1,184 @@
+use crate::{
+    immutable::{ImmutableMemory, ImmutableStatus},
+    FaultKind, Instruction,
+};
+
+/// A pre-decoded instruction. `nop` and `out` both become `Add(0)`, since a run
+/// which doesn't report outputs can't tell them apart.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Op {
+    Add(isize),
+    Jump(isize),
+    In,
+}
+
+impl From<&Instruction> for Op {
+    fn from(instruction: &Instruction) -> Self {
+        match *instruction {
+            Instruction::Noop(_) | Instruction::Output(_) => Op::Add(0),
+            Instruction::Accumulate(a) => Op::Add(a),
+            Instruction::Jump(j) => Op::Jump(j),
+            Instruction::Input(_) => Op::In,
+        }
+    }
+}
+
+/// A program decoded once, to be run many times.
+/// Runs work on plain integers and a bitmap of visited indices, and only build a
+/// `Memory` again when they stop.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Compiled {
+    pub ops: Vec<Op>,
+}
+
+enum Stop {
+    Halted,
+    InvalidIndex,
+    WouldRevisit,
+    AwaitingInput,
+    NegativeIndex,
+    PastEnd,
+    Overflow,
+}
+
+impl Compiled {
+    pub fn new(instructions: &[Instruction]) -> Self {
+        Self {
+            ops: instructions.iter().map(Op::from).collect(),
+        }
+    }
+    /// Exactly what `immutable_memory.run()` would do, provided `ops` was compiled from its
+    /// instructions.
+    pub fn run(&self, mut immutable_memory: ImmutableMemory) -> ImmutableStatus {
+        let len = self.ops.len();
+        // One more than the instructions, for the halt point
+        let mut visited = vec![false; len + 1];
+        for index in immutable_memory.visited_indices.iter() {
+            if *index >= 0 && *index as usize <= len {
+                visited[*index as usize] = true;
+            }
+        }
+        let memory = &mut immutable_memory.memory;
+        let mut accumulator = memory.accumulator;
+        let stop = match memory.index {
+            i if i >= 0 && i as usize <= len => {
+                let mut index = i as usize;
+                let stop = loop {
+                    let (next_accumulator, next_index) = match self.ops.get(index) {
+                        None => break Stop::Halted,
+                        Some(Op::Add(a)) => match accumulator.checked_add(*a) {
+                            Some(next) => (next, index + 1),
+                            None => break Stop::Overflow,
+                        },
+                        Some(Op::Jump(j)) => match (index as isize).checked_add(*j) {
+                            Some(next) if next < 0 => break Stop::NegativeIndex,
+                            Some(next) if next as usize > len => break Stop::PastEnd,
+                            Some(next) => (accumulator, next as usize),
+                            None => break Stop::Overflow,
+                        },
+                        Some(Op::In) => match memory.inputs.pop_front() {
+                            Some(value) => (value, index + 1),
+                            None => break Stop::AwaitingInput,
+                        },
+                    };
+                    if visited[next_index] {
+                        if let Some(Op::In) = self.ops.get(index) {
+                            memory.inputs.push_front(next_accumulator)
+                        }
+                        break Stop::WouldRevisit;
+                    }
+                    visited[next_index] = true;
+                    accumulator = next_accumulator;
+                    index = next_index;
+                };
+                memory.index = index as isize;
+                stop
+            }
+            _ => Stop::InvalidIndex,
+        };
+        memory.accumulator = accumulator;
+        for (index, v) in visited.into_iter().enumerate() {
+            if v {
+                immutable_memory.visited_indices.insert(index as isize);
+            }
+        }
+
+        let fault = |kind: FaultKind, immutable_memory: ImmutableMemory| {
+            let fault = kind.at(immutable_memory.memory.clone());
+            ImmutableStatus::WouldFault(immutable_memory, fault)
+        };
+        match stop {
+            Stop::Halted => ImmutableStatus::Halted(immutable_memory),
+            Stop::InvalidIndex => ImmutableStatus::InvalidIndex(immutable_memory),
+            Stop::WouldRevisit => ImmutableStatus::WouldRevisit(immutable_memory),
+            Stop::AwaitingInput => ImmutableStatus::AwaitingInput(immutable_memory),
+            Stop::NegativeIndex => fault(FaultKind::NegativeIndex, immutable_memory),
+            Stop::PastEnd => fault(FaultKind::PastEnd, immutable_memory),
+            Stop::Overflow => fault(FaultKind::Overflow, immutable_memory),
+        }
+    }
+}
+
+impl ImmutableMemory {
+    /// As `run`, but without cloning the program on every step.
+    pub fn run_fast(self) -> ImmutableStatus {
+        Compiled::new(&self.memory.instructions).run(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn same(a: &ImmutableStatus, b: &ImmutableStatus) -> bool {
+        use ImmutableStatus::*;
+        match (a, b) {
+            (Running(a), Running(b))
+            | (Halted(a), Halted(b))
+            | (InvalidIndex(a), InvalidIndex(b))
+            | (WouldRevisit(a), WouldRevisit(b))
+            | (AwaitingInput(a), AwaitingInput(b)) => a == b,
+            (WouldFault(a, x), WouldFault(b, y)) => a == b && x == y,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn example() {
+        let immutable_memory: ImmutableMemory =
+            "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n"
+                .parse()
+                .unwrap();
+        match immutable_memory.run_fast() {
+            ImmutableStatus::WouldRevisit(state) => assert_eq!(state.memory.accumulator, 5),
+            _ => panic!(),
+        }
+    }
+
+    fn instruction() -> impl Strategy<Value = Instruction> {
+        prop_oneof![
+            (-6..6isize).prop_map(Instruction::Noop),
+            prop_oneof![(-6..6isize), Just(isize::MAX), Just(isize::MIN)]
+                .prop_map(Instruction::Accumulate),
+            (-6..6isize).prop_map(Instruction::Jump),
+            (-6..6isize).prop_map(Instruction::Input),
+            (-6..6isize).prop_map(Instruction::Output),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn agrees_with_run(
+            instructions in prop::collection::vec(instruction(), 0..24),
+            index in -2..26isize,
+            inputs in prop::collection::vec(-6..6isize, 0..4),
+        ) {
+            let mut immutable_memory = ImmutableMemory::new(instructions);
+            immutable_memory.memory.index = index;
+            immutable_memory.memory.inputs = inputs.into();
+            let (fast, slow) = (immutable_memory.clone().run_fast(), immutable_memory.run());
+            prop_assert!(same(&fast, &slow), "{:?} != {:?}", fast, slow);
+        }
+    }
+}
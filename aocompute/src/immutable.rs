@@ -1,25 +1,37 @@
-use crate::{Instruction, Memory, ParseInstructionError, Status};
+use crate::{
+    observer::{Visited, VmObserver},
+    trace::Trace,
+    ExecutionFault, Instruction, Memory, ParseMemoryError, Status,
+};
 use std::collections::HashSet;
 use std::{convert::From, error, fs, path, str::FromStr};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ImmutableMemory {
     pub memory: Memory,
-    visited_indices: HashSet<isize>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "crate::snapshot::sorted"))]
+    pub(crate) visited_indices: HashSet<isize>,
 }
 
 impl ImmutableMemory {
-    pub fn execute_instruction(mut self) -> Self {
+    pub fn try_execute_instruction(mut self) -> Result<Self, ExecutionFault> {
         let old_memory = self.memory.clone();
-        let new_memory = self.memory.execute_instruction();
+        let new_memory = self.memory.try_execute_instruction()?;
         assert_eq!(
             old_memory.instructions, new_memory.instructions,
             "Assumption broken: instructions have mutated!"
         );
         self.visited_indices.insert(new_memory.index);
-        Self {
+        Ok(Self {
             memory: new_memory,
             visited_indices: self.visited_indices,
+        })
+    }
+    pub fn execute_instruction(self) -> Self {
+        match self.try_execute_instruction() {
+            Ok(immutable_memory) => immutable_memory,
+            Err(fault) => panic!("{}", fault),
         }
     }
     pub fn new<I>(instructions: I) -> Self
@@ -38,18 +50,52 @@ impl ImmutableMemory {
             visited_indices: HashSet::new(),
         })
     }
-    pub fn run(mut self) -> ImmutableStatus {
-        loop {
-            match ImmutableStatus::from(self.clone()) {
-                ImmutableStatus::Running(_) => self = self.execute_instruction(),
-                _ => return ImmutableStatus::from(self),
+    /// Run until the next instruction would move to an index that's been moved to before.
+    /// This is `Memory::run_observed` with a `Visited` observer, carrying on past outputs.
+    pub fn run(self) -> ImmutableStatus {
+        self.run_observed(())
+    }
+    /// As `run`, recording every step into `trace`.
+    pub fn run_traced(self, trace: &mut Trace) -> ImmutableStatus {
+        self.run_observed(trace)
+    }
+    /// As `run`, with `observer` watching alongside `Visited`.
+    fn run_observed<O: VmObserver>(self, observer: O) -> ImmutableStatus {
+        let mut observers = (
+            Visited {
+                indices: self.visited_indices,
+            },
+            observer,
+        );
+        let mut memory = self.memory;
+        let status = loop {
+            match memory.run_observed(&mut observers) {
+                Status::Emitted(_, m) => memory = m,
+                status => break status,
+            }
+        };
+        let visited_indices = observers.0.indices;
+        let immutable_memory = |memory| Self {
+            memory,
+            visited_indices,
+        };
+        match status {
+            Status::Running(m) => ImmutableStatus::WouldRevisit(immutable_memory(m)),
+            Status::Halted(m) => ImmutableStatus::Halted(immutable_memory(m)),
+            Status::InvalidIndex(m) => ImmutableStatus::InvalidIndex(immutable_memory(m)),
+            Status::AwaitingInput(m) => ImmutableStatus::AwaitingInput(immutable_memory(m)),
+            Status::Faulted(fault) => {
+                ImmutableStatus::WouldFault(immutable_memory(fault.memory().clone()), fault)
+            }
+            Status::Emitted(..) | Status::OutOfFuel(_) | Status::TimedOut(_) => {
+                unreachable!("Outputs are skipped, and there are no limits")
             }
         }
     }
 }
 
 impl FromStr for ImmutableMemory {
-    type Err = ParseInstructionError;
+    type Err = ParseMemoryError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         Ok(Self {
             memory: s.parse()?,
@@ -63,21 +109,31 @@ pub enum ImmutableStatus {
     Running(ImmutableMemory),
     Halted(ImmutableMemory),
     InvalidIndex(ImmutableMemory),
+    /// The next index has been executed before. That needn't be a loop - see `Memory::find_cycle`
     WouldRevisit(ImmutableMemory),
+    WouldFault(ImmutableMemory, ExecutionFault),
+    /// Stopped at an `in` with nothing in `memory.inputs`
+    AwaitingInput(ImmutableMemory),
 }
 
 impl From<ImmutableMemory> for ImmutableStatus {
     fn from(immutable_memory: ImmutableMemory) -> Self {
-        // We want to stop if the **next** instruction would be a revisit, so do a lookahead
-        let lookahead = immutable_memory.memory.clone().execute_instruction();
-        if immutable_memory.visited_indices.contains(&lookahead.index) {
-            return ImmutableStatus::WouldRevisit(immutable_memory);
-        } else {
-            match Status::from(immutable_memory.memory.clone()) {
-                Status::Running(_) => ImmutableStatus::Running(immutable_memory),
-                Status::Halted(_) => ImmutableStatus::Halted(immutable_memory),
-                Status::InvalidIndex(_) => ImmutableStatus::InvalidIndex(immutable_memory),
+        match Status::from(immutable_memory.memory.clone()) {
+            Status::Halted(_) => ImmutableStatus::Halted(immutable_memory),
+            Status::InvalidIndex(_) => ImmutableStatus::InvalidIndex(immutable_memory),
+            Status::Faulted(fault) => ImmutableStatus::WouldFault(immutable_memory, fault),
+            Status::AwaitingInput(_) => ImmutableStatus::AwaitingInput(immutable_memory),
+            Status::OutOfFuel(_) | Status::TimedOut(_) => {
+                unreachable!("Classifying memory doesn't run anything")
             }
+            // We want to stop if the **next** instruction would be a revisit, so do a lookahead
+            Status::Running(memory) | Status::Emitted(_, memory) => match memory.lookahead() {
+                Ok((_, index)) if immutable_memory.visited_indices.contains(&index) => {
+                    ImmutableStatus::WouldRevisit(immutable_memory)
+                }
+                Ok(_) => ImmutableStatus::Running(immutable_memory),
+                Err(kind) => ImmutableStatus::WouldFault(immutable_memory, kind.at(memory)),
+            },
         }
     }
 }
@@ -85,6 +141,12 @@ impl From<ImmutableMemory> for ImmutableStatus {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        arbitrary::{Params, Shape},
+        steps::{Progress, State},
+    };
+    use proptest::prelude::*;
+
     #[test]
     fn test_run() {
         let immutable_memory: ImmutableMemory =
@@ -96,4 +158,100 @@ mod tests {
             _ => panic!(),
         }
     }
+    #[test]
+    fn test_run_faults() {
+        let immutable_memory: ImmutableMemory = "nop +0\njmp -2\n".parse().unwrap();
+        match immutable_memory.run() {
+            ImmutableStatus::WouldFault(state, ExecutionFault::NegativeIndex(_)) => {
+                assert_eq!(state.memory.index, 1)
+            }
+            _ => panic!(),
+        }
+        let immutable_memory: ImmutableMemory = "acc +1\njmp +2\n".parse().unwrap();
+        match immutable_memory.run() {
+            ImmutableStatus::WouldFault(state, ExecutionFault::PastEnd(_)) => {
+                assert_eq!(state.memory.accumulator, 1)
+            }
+            _ => panic!(),
+        }
+    }
+    #[test]
+    fn test_run_awaits_input() {
+        let immutable_memory: ImmutableMemory = "acc +1\nin +0\nacc +1\n".parse().unwrap();
+        let mut state = match immutable_memory.run() {
+            ImmutableStatus::AwaitingInput(state) => state,
+            _ => panic!(),
+        };
+        assert_eq!(state.memory.index, 1);
+        state.memory.inputs.push_back(41);
+        match state.run() {
+            ImmutableStatus::Halted(state) => assert_eq!(state.memory.accumulator, 42),
+            _ => panic!(),
+        }
+    }
+
+    /// The states of a `Memory` run of at most 1000 steps, and how it stopped, if it did.
+    fn bounded(memory: Memory) -> (Vec<State>, Option<Status>) {
+        let mut states = Vec::new();
+        let mut end = None;
+        for progress in memory.steps().take(1001) {
+            match progress {
+                Progress::At(state) => states.push(state),
+                Progress::Stopped(status) => end = Some(status),
+            }
+        }
+        (states, end)
+    }
+
+    /// `ImmutableMemory::run` stops where `Memory` does, or wherever `Memory` is at the
+    /// same step if it's about to revisit an index.
+    fn agree(memory: Memory) -> Result<ImmutableStatus, TestCaseError> {
+        let (states, end) = bounded(memory.clone());
+        let status = ImmutableMemory {
+            memory,
+            visited_indices: HashSet::new(),
+        }
+        .run();
+        match (&status, &end) {
+            (ImmutableStatus::Halted(m), Some(Status::Halted(end)))
+            | (ImmutableStatus::AwaitingInput(m), Some(Status::AwaitingInput(end)))
+            | (ImmutableStatus::InvalidIndex(m), Some(Status::InvalidIndex(end))) => {
+                prop_assert_eq!(&m.memory, end);
+                prop_assert_eq!(states.len(), m.visited_indices.len() + 1);
+            }
+            (ImmutableStatus::WouldFault(m, fault), Some(Status::Faulted(end))) => {
+                prop_assert_eq!(fault, end);
+                prop_assert_eq!(states.len(), m.visited_indices.len() + 1);
+            }
+            (ImmutableStatus::WouldRevisit(m), end) => {
+                prop_assert!(!matches!(end, Some(Status::Halted(_))));
+                let state = states[m.visited_indices.len()];
+                prop_assert_eq!(
+                    (state.index, state.accumulator),
+                    (m.memory.index, m.memory.accumulator)
+                );
+            }
+            _ => prop_assert!(false, "{:?} and {:?} disagree", status, end),
+        }
+        Ok(status)
+    }
+
+    proptest! {
+        #[test]
+        fn agrees_with_memory(memory in any::<Memory>()) {
+            agree(memory)?;
+        }
+        #[test]
+        fn agrees_on_halting(
+            memory in any_with::<Memory>(Params::default().with_shape(Shape::Halting))
+        ) {
+            prop_assert!(matches!(agree(memory)?, ImmutableStatus::Halted(_)));
+        }
+        #[test]
+        fn agrees_on_looping(
+            memory in any_with::<Memory>(Params::default().with_shape(Shape::Looping))
+        ) {
+            prop_assert!(matches!(agree(memory)?, ImmutableStatus::WouldRevisit(_)));
+        }
+    }
 }
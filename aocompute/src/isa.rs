@@ -0,0 +1,521 @@
+use crate::{words, Instruction, Memory, ParseInstructionError, ParseMemoryError, ParseReason};
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap, VecDeque},
+    error, fmt,
+    sync::Arc,
+};
+
+/// The machine state an opcode can see and change.
+/// Register 0 is the accumulator, the rest are in `registers`.
+/// Registers and data which have never been written hold 0.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Cpu {
+    pub accumulator: isize,
+    pub index: isize,
+    pub registers: Vec<isize>,
+    pub data: BTreeMap<isize, isize>,
+    /// Read by `in`
+    pub inputs: VecDeque<isize>,
+    /// Written by `out`
+    pub outputs: Vec<isize>,
+    /// How many instructions the program has, so that `jump` can refuse to leave it.
+    /// Kept up to date by `Machine`.
+    pub program_len: usize,
+}
+
+impl Cpu {
+    /// Move `index` by `offset`, unless that would leave the program.
+    /// Landing just past the end is fine - that's how a program halts.
+    pub fn jump(&mut self, offset: isize) -> Result<(), Fault> {
+        self.index = match self.index.checked_add(offset) {
+            Some(i) if i < 0 => return Err(Fault::NegativeIndex),
+            Some(i) if i as usize > self.program_len => return Err(Fault::PastEnd),
+            Some(i) => i,
+            None => return Err(Fault::Overflow),
+        };
+        Ok(())
+    }
+    pub fn register(&self, register: usize) -> isize {
+        match register {
+            0 => self.accumulator,
+            r => self.registers.get(r - 1).copied().unwrap_or(0),
+        }
+    }
+    pub fn set_register(&mut self, register: usize, value: isize) {
+        match register {
+            0 => self.accumulator = value,
+            r => {
+                if self.registers.len() < r {
+                    self.registers.resize(r, 0)
+                }
+                self.registers[r - 1] = value
+            }
+        }
+    }
+    pub fn load(&self, address: isize) -> isize {
+        self.data.get(&address).copied().unwrap_or(0)
+    }
+    pub fn store(&mut self, address: isize, value: isize) {
+        self.data.insert(address, value);
+    }
+}
+
+/// Why a `Machine` couldn't execute an instruction.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Fault {
+    NegativeIndex,
+    PastEnd,
+    Overflow,
+    /// Nothing in `inputs` to read
+    NeedsInput,
+    /// For opcodes to describe their own failures
+    Other(String),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::NegativeIndex => write!(f, "would move to a negative index"),
+            Fault::PastEnd => write!(f, "would move past the end of the program"),
+            Fault::Overflow => write!(f, "would overflow"),
+            Fault::NeedsInput => write!(f, "needs input"),
+            Fault::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl error::Error for Fault {}
+
+/// A mnemonic, how to read its operands, and what it does.
+/// Operands are decoded to integers once, when the program is parsed.
+pub trait Opcode: Any + fmt::Debug + Send + Sync {
+    fn mnemonic(&self) -> &str;
+    fn arity(&self) -> usize {
+        1
+    }
+    /// Decode the operand at `position` - by default, a signed integer.
+    fn parse_operand(&self, _position: usize, word: &str) -> Result<isize, ParseReason> {
+        word.parse().map_err(ParseReason::BadInteger)
+    }
+    /// The inverse of `parse_operand`.
+    fn render_operand(&self, _position: usize, operand: isize) -> String {
+        format!("{:+}", operand)
+    }
+    /// Update `cpu`, including moving `index` on.
+    /// Leave `cpu` untouched if returning an error - `jump` checks where it lands, so jump
+    /// before making any other changes, unless only moving on by 1.
+    fn execute(&self, operands: &[isize], cpu: &mut Cpu) -> Result<(), Fault>;
+}
+
+#[derive(Debug)]
+pub struct Nop;
+impl Opcode for Nop {
+    fn mnemonic(&self) -> &str {
+        "nop"
+    }
+    fn execute(&self, _operands: &[isize], cpu: &mut Cpu) -> Result<(), Fault> {
+        cpu.jump(1)
+    }
+}
+
+#[derive(Debug)]
+pub struct Acc;
+impl Opcode for Acc {
+    fn mnemonic(&self) -> &str {
+        "acc"
+    }
+    fn execute(&self, operands: &[isize], cpu: &mut Cpu) -> Result<(), Fault> {
+        let accumulator = cpu.accumulator.checked_add(operands[0]);
+        cpu.accumulator = accumulator.ok_or(Fault::Overflow)?;
+        cpu.jump(1)
+    }
+}
+
+#[derive(Debug)]
+pub struct Jmp;
+impl Opcode for Jmp {
+    fn mnemonic(&self) -> &str {
+        "jmp"
+    }
+    fn execute(&self, operands: &[isize], cpu: &mut Cpu) -> Result<(), Fault> {
+        cpu.jump(operands[0])
+    }
+}
+
+#[derive(Debug)]
+pub struct In;
+impl Opcode for In {
+    fn mnemonic(&self) -> &str {
+        "in"
+    }
+    fn execute(&self, _operands: &[isize], cpu: &mut Cpu) -> Result<(), Fault> {
+        let value = *cpu.inputs.front().ok_or(Fault::NeedsInput)?;
+        cpu.jump(1)?;
+        cpu.inputs.pop_front();
+        cpu.accumulator = value;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Out;
+impl Opcode for Out {
+    fn mnemonic(&self) -> &str {
+        "out"
+    }
+    fn execute(&self, _operands: &[isize], cpu: &mut Cpu) -> Result<(), Fault> {
+        cpu.jump(1)?;
+        cpu.outputs.push(cpu.accumulator);
+        Ok(())
+    }
+}
+
+/// An opcode with its decoded operands.
+#[derive(Clone)]
+pub struct Op {
+    pub opcode: Arc<dyn Opcode>,
+    pub operands: Vec<isize>,
+}
+
+impl fmt::Debug for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Op({:?}, {:?})", self.opcode.mnemonic(), self.operands)
+    }
+}
+
+/// Ops are equal if their opcodes are the same type with the same mnemonic, and their operands
+/// match - so opcodes from different registries compare equal, but `nop` can't pass for a
+/// custom opcode called `nop`.
+impl PartialEq for Op {
+    fn eq(&self, other: &Self) -> bool {
+        let (opcode, other_opcode): (&dyn Any, &dyn Any) = (&*self.opcode, &*other.opcode);
+        opcode.type_id() == other_opcode.type_id()
+            && self.opcode.mnemonic() == other.opcode.mnemonic()
+            && self.operands == other.operands
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.opcode.mnemonic())?;
+        for (position, operand) in self.operands.iter().enumerate() {
+            write!(f, " {}", self.opcode.render_operand(position, *operand))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&Instruction> for Op {
+    fn from(instruction: &Instruction) -> Self {
+        let (opcode, operand): (Arc<dyn Opcode>, isize) = match *instruction {
+            Instruction::Noop(n) => (Arc::new(Nop), n),
+            Instruction::Accumulate(a) => (Arc::new(Acc), a),
+            Instruction::Jump(j) => (Arc::new(Jmp), j),
+            Instruction::Input(p) => (Arc::new(In), p),
+            Instruction::Output(p) => (Arc::new(Out), p),
+        };
+        Self {
+            opcode,
+            operands: vec![operand],
+        }
+    }
+}
+
+/// The opcodes a program may use, by mnemonic.
+/// `Registry::default()` is the `nop`, `acc`, `jmp`, `in` and `out` instruction set.
+#[derive(Debug, Clone)]
+pub struct Registry {
+    opcodes: HashMap<String, Arc<dyn Opcode>>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Nop);
+        registry.register(Acc);
+        registry.register(Jmp);
+        registry.register(In);
+        registry.register(Out);
+        registry
+    }
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Self {
+            opcodes: HashMap::new(),
+        }
+    }
+    /// Add `opcode`, returning whatever previously had its mnemonic.
+    pub fn register<O: Opcode + 'static>(&mut self, opcode: O) -> Option<Arc<dyn Opcode>> {
+        self.opcodes
+            .insert(opcode.mnemonic().to_string(), Arc::new(opcode))
+    }
+    pub fn get(&self, mnemonic: &str) -> Option<Arc<dyn Opcode>> {
+        self.opcodes.get(mnemonic).cloned()
+    }
+    /// Errors are reported as if `line` were the first line of a program.
+    pub fn parse_op(&self, line: &str) -> Result<Op, ParseInstructionError> {
+        let error = |columns, reason| ParseInstructionError {
+            line: 1,
+            columns,
+            reason,
+        };
+        let words = words(line);
+        let ((columns, mnemonic), operands) = match words.split_first() {
+            Some(split) => split,
+            None => return Err(error(0..line.len(), ParseReason::MissingMnemonic)),
+        };
+        let opcode = match self.get(mnemonic) {
+            Some(opcode) => opcode,
+            None => {
+                return Err(error(
+                    columns.clone(),
+                    ParseReason::UnknownMnemonic(mnemonic.to_string()),
+                ))
+            }
+        };
+        if operands.len() != opcode.arity() {
+            return Err(error(
+                match (operands.first(), operands.last()) {
+                    (Some((first, _)), Some((last, _))) => first.start..last.end,
+                    _ => columns.end..line.len(),
+                },
+                ParseReason::WrongOperandCount {
+                    expected: opcode.arity(),
+                    found: operands.len(),
+                },
+            ));
+        }
+        let operands = operands
+            .iter()
+            .enumerate()
+            .map(|(position, (columns, word))| {
+                opcode
+                    .parse_operand(position, word)
+                    .map_err(|reason| error(columns.clone(), reason))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Op { opcode, operands })
+    }
+    /// Parse one instruction per line, like `Memory::from_str`, reporting every bad line.
+    pub fn parse(&self, source: &str) -> Result<Machine, ParseMemoryError> {
+        let mut instructions = Vec::new();
+        let mut errors = Vec::new();
+        for (number, line) in source.lines().enumerate() {
+            match self.parse_op(line) {
+                Ok(op) => instructions.push(op),
+                Err(mut e) => {
+                    e.line = number + 1;
+                    errors.push(e)
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(Machine::new(instructions)),
+            false => Err(ParseMemoryError { errors }),
+        }
+    }
+}
+
+/// Like `Memory`, but running whatever opcodes it was built with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Machine {
+    pub instructions: Vec<Op>,
+    pub cpu: Cpu,
+}
+
+impl Machine {
+    pub fn new<I>(instructions: I) -> Self
+    where
+        I: IntoIterator<Item = Op>,
+    {
+        let instructions: Vec<Op> = instructions.into_iter().collect();
+        Self {
+            cpu: Cpu {
+                program_len: instructions.len(),
+                ..Cpu::default()
+            },
+            instructions,
+        }
+    }
+    pub fn is_halted(&self) -> bool {
+        self.cpu.index >= 0 && self.cpu.index as usize == self.instructions.len()
+    }
+    /// Execute the instruction at `index`.
+    /// On a fault nothing changes, as long as the opcode keeps to the rules of
+    /// `Opcode::execute`. If it sets `index` somewhere invalid directly, only `index` is put back.
+    pub fn step(&mut self) -> Result<(), Fault> {
+        let index = self.cpu.index;
+        let op = match index {
+            i if i < 0 => return Err(Fault::NegativeIndex),
+            i => self.instructions.get(i as usize).ok_or(Fault::PastEnd)?,
+        };
+        self.cpu.program_len = self.instructions.len();
+        op.opcode.execute(&op.operands, &mut self.cpu)?;
+        let fault = match self.cpu.index {
+            i if i < 0 => Fault::NegativeIndex,
+            i if i as usize > self.instructions.len() => Fault::PastEnd,
+            _ => return Ok(()),
+        };
+        self.cpu.index = index;
+        Err(fault)
+    }
+    /// Step until halted, or `max_steps` have been taken.
+    /// Returns whether the machine halted.
+    pub fn run(&mut self, max_steps: usize) -> Result<bool, Fault> {
+        for _ in 0..max_steps {
+            if self.is_halted() {
+                return Ok(true);
+            }
+            self.step()?;
+        }
+        Ok(self.is_halted())
+    }
+}
+
+impl From<&Memory> for Machine {
+    fn from(memory: &Memory) -> Self {
+        Self {
+            instructions: memory.instructions.iter().map(Op::from).collect(),
+            cpu: Cpu {
+                accumulator: memory.accumulator,
+                index: memory.index,
+                inputs: memory.inputs.clone(),
+                program_len: memory.instructions.len(),
+                ..Cpu::default()
+            },
+        }
+    }
+}
+
+impl fmt::Display for Machine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for op in self.instructions.iter() {
+            writeln!(f, "{}", op)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\nnop -4\nacc +6\n";
+
+    /// Multiplies the accumulator.
+    #[derive(Debug)]
+    struct Mul;
+    impl Opcode for Mul {
+        fn mnemonic(&self) -> &str {
+            "mul"
+        }
+        fn execute(&self, operands: &[isize], cpu: &mut Cpu) -> Result<(), Fault> {
+            let accumulator = cpu.accumulator.checked_mul(operands[0]);
+            cpu.accumulator = accumulator.ok_or(Fault::Overflow)?;
+            cpu.jump(1)
+        }
+    }
+
+    /// Jumps to an absolute index, written in hex.
+    #[derive(Debug)]
+    struct Goto;
+    impl Opcode for Goto {
+        fn mnemonic(&self) -> &str {
+            "goto"
+        }
+        fn parse_operand(&self, _position: usize, word: &str) -> Result<isize, ParseReason> {
+            isize::from_str_radix(word.trim_start_matches("0x"), 16)
+                .map_err(ParseReason::BadInteger)
+        }
+        fn render_operand(&self, _position: usize, operand: isize) -> String {
+            format!("{:#x}", operand)
+        }
+        fn execute(&self, operands: &[isize], cpu: &mut Cpu) -> Result<(), Fault> {
+            cpu.index = operands[0];
+            Ok(())
+        }
+    }
+
+    /// Jumps, then increments the accumulator.
+    #[derive(Debug)]
+    struct IncJmp;
+    impl Opcode for IncJmp {
+        fn mnemonic(&self) -> &str {
+            "incjmp"
+        }
+        fn execute(&self, operands: &[isize], cpu: &mut Cpu) -> Result<(), Fault> {
+            cpu.jump(operands[0])?;
+            cpu.accumulator += 1;
+            Ok(())
+        }
+    }
+
+    /// Not the usual `nop`.
+    #[derive(Debug)]
+    struct Halt;
+    impl Opcode for Halt {
+        fn mnemonic(&self) -> &str {
+            "nop"
+        }
+        fn execute(&self, _operands: &[isize], _cpu: &mut Cpu) -> Result<(), Fault> {
+            Err(Fault::Other(String::from("halt")))
+        }
+    }
+
+    #[test]
+    fn default_matches_memory() {
+        let memory: Memory = EXAMPLE.parse().unwrap();
+        let mut machine = Registry::default().parse(EXAMPLE).unwrap();
+        assert_eq!(machine, Machine::from(&memory));
+        assert_eq!(machine.to_string(), memory.to_string());
+        assert_eq!(machine.run(100), Ok(true));
+        assert_eq!(machine.cpu.accumulator, 8);
+
+        let mut registry = Registry::default();
+        registry.register(Halt);
+        assert_ne!(registry.parse(EXAMPLE).unwrap(), Machine::from(&memory));
+    }
+    #[test]
+    fn custom_opcodes() {
+        let mut registry = Registry::default();
+        registry.register(Mul);
+        registry.register(Goto);
+        let source = "acc +3\ngoto 0x3\nacc +100\nmul -2\n";
+        let mut machine = registry.parse(source).unwrap();
+        assert_eq!(machine.to_string(), source);
+        assert_eq!(machine.run(100), Ok(true));
+        assert_eq!(machine.cpu.accumulator, -6);
+
+        let e = Registry::default().parse(source).unwrap_err();
+        let reasons: Vec<ParseReason> = e.errors.into_iter().map(|e| e.reason).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                ParseReason::UnknownMnemonic(String::from("goto")),
+                ParseReason::UnknownMnemonic(String::from("mul"))
+            ]
+        );
+    }
+    #[test]
+    fn faults() {
+        let mut machine = Registry::default().parse("nop +0\njmp -2\n").unwrap();
+        assert_eq!(machine.run(100), Err(Fault::NegativeIndex));
+        assert_eq!(machine.cpu.index, 1);
+        let mut machine = Registry::default().parse("jmp +0\n").unwrap();
+        assert_eq!(machine.run(100), Ok(false));
+        // Steps don't copy the state, so long runs stay quick as outputs pile up
+        let mut machine = Registry::default().parse("out +0\njmp -1\n").unwrap();
+        assert_eq!(machine.run(100_000), Ok(false));
+        assert_eq!(machine.cpu.outputs.len(), 50_000);
+        // Jumping first leaves the accumulator alone on a bad jump
+        let mut registry = Registry::default();
+        registry.register(IncJmp);
+        let mut machine = registry.parse("acc +3\nincjmp -5\n").unwrap();
+        assert_eq!(machine.run(100), Err(Fault::NegativeIndex));
+        assert_eq!((machine.cpu.index, machine.cpu.accumulator), (1, 3));
+    }
+}
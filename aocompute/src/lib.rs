@@ -1,26 +1,171 @@
-use std::{convert::From, error, fmt, fs, iter::Iterator, num, path, str::FromStr};
+use observer::{Control, VmObserver};
+use std::{
+    collections::VecDeque,
+    convert::From,
+    error, fmt, fs,
+    iter::Iterator,
+    num, ops, path,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 use Instruction::*;
 use Status::*;
+#[cfg(any(test, feature = "proptest"))]
+pub mod arbitrary;
+pub mod arithmetic;
+pub mod assembler;
+pub mod bytecode;
+pub mod cfg;
+pub mod cycle;
+pub mod debugger;
+pub mod fast;
 pub mod immutable;
+pub mod isa;
+pub mod mutate;
+pub mod network;
+pub mod observer;
+pub mod optimize;
+pub mod profile;
+pub mod ranges;
+pub mod registers;
+pub mod repair;
+#[cfg(feature = "serde")]
+pub mod snapshot;
+pub mod steps;
+pub mod trace;
+mod varint;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Memory {
     pub instructions: Vec<Instruction>,
     pub accumulator: isize,
     pub index: isize,
+    /// Values waiting to be read by `in` instructions
+    pub inputs: VecDeque<isize>,
 }
 
 impl Memory {
-    pub fn execute_instruction(mut self) -> Self {
-        match self.instructions[self.index as usize] {
-            Noop(_) => self.index += 1,
-            Accumulate(a) => {
-                self.accumulator += a;
-                self.index += 1
-            }
-            Jump(j) => self.index += j,
+    /// The accumulator and index that executing the instruction at `index` would leave,
+    /// without executing it - or the kind of fault it would be.
+    pub fn lookahead(&self) -> Result<(isize, isize), FaultKind> {
+        arithmetic::lookahead(
+            &self.instructions,
+            self.index,
+            &self.accumulator,
+            &self.inputs,
+            arithmetic::Mode::Checked,
+        )
+    }
+    /// Execute the instruction at `index`, or describe why that isn't possible.
+    /// Landing on the instruction just past the end is fine - that's how a program halts.
+    pub fn try_execute_instruction(mut self) -> Result<Self, ExecutionFault> {
+        let (accumulator, index) = match self.lookahead() {
+            Ok(next) => next,
+            Err(kind) => return Err(kind.at(self)),
         };
-        self
+        if let Some(Input(_)) = self.instructions.get(self.index as usize) {
+            self.inputs.pop_front();
+        }
+        self.accumulator = accumulator;
+        self.index = index;
+        Ok(self)
+    }
+    pub fn execute_instruction(self) -> Self {
+        match self.try_execute_instruction() {
+            Ok(memory) => memory,
+            Err(fault) => panic!("{}", fault),
+        }
+    }
+    /// Execute a single instruction, and report where that left us.
+    /// Executing an `out` reports the value it wrote.
+    pub fn step(self) -> Status {
+        match Status::from(self) {
+            Running(memory) => {
+                let output = match memory.instructions[memory.index as usize] {
+                    Output(_) => Some(memory.accumulator),
+                    _ => None,
+                };
+                match (memory.try_execute_instruction(), output) {
+                    (Ok(memory), Some(value)) => Emitted(value, memory),
+                    (Ok(memory), None) => Status::from(memory),
+                    (Err(fault), _) => Faulted(fault),
+                }
+            }
+            status => status,
+        }
+    }
+    /// Step until the program halts, faults, needs input or produces output.
+    /// A host can drive the program as a coroutine by pushing to `inputs` and calling this again.
+    /// Never returns if the program loops forever - see `run_with_budget`, or `ImmutableMemory`.
+    pub fn run(self) -> Status {
+        self.run_observed(&mut ())
+    }
+    /// As `run`, calling `observer` around each instruction.
+    /// If the observer asks to stop, returns `Running` with the next instruction unexecuted -
+    /// unless the last instruction was an `out`, when the run ends with `Emitted` as usual.
+    pub fn run_observed<O: VmObserver>(mut self, observer: &mut O) -> Status {
+        let mut control = Control::Continue;
+        loop {
+            self = match Status::from(self) {
+                Running(memory) => memory,
+                Halted(memory) => {
+                    observer.on_halt(&memory);
+                    return Halted(memory);
+                }
+                status => return status,
+            };
+            if control == Control::Stop || observer.before_step(&self) == Control::Stop {
+                return Running(self);
+            }
+            let (index, accumulator) = (self.index, self.accumulator);
+            let output = match self.instructions[index as usize] {
+                Output(_) => Some(accumulator),
+                _ => None,
+            };
+            self = match self.try_execute_instruction() {
+                Ok(memory) => memory,
+                Err(fault) => {
+                    observer.on_fault(&fault);
+                    return Faulted(fault);
+                }
+            };
+            control = observer.after_step(index, accumulator, &self);
+            if let Some(value) = output {
+                return Emitted(value, self);
+            }
+        }
+    }
+    /// As `run`, but stops with `OutOfFuel` once `max_steps` instructions have executed.
+    pub fn run_with_budget(self, max_steps: usize) -> Status {
+        self.run_with_limits(Some(max_steps), None)
+    }
+    /// As `run`, with an optional step budget, and an optional `timeout` after which it stops
+    /// with `TimedOut`. The clock is only read every few thousand steps.
+    pub fn run_with_limits(
+        mut self,
+        max_steps: Option<usize>,
+        timeout: Option<Duration>,
+    ) -> Status {
+        let start = Instant::now();
+        let mut steps = 0;
+        loop {
+            self = match Status::from(self) {
+                Running(memory) => memory,
+                status => return status,
+            };
+            if max_steps == Some(steps) {
+                return OutOfFuel(self);
+            }
+            if steps % 4096 == 0 && timeout.is_some_and(|t| start.elapsed() >= t) {
+                return TimedOut(self);
+            }
+            match self.step() {
+                Running(memory) => self = memory,
+                status => return status,
+            }
+            steps += 1;
+        }
     }
     pub fn new<I>(instructions: I) -> Self
     where
@@ -30,6 +175,7 @@ impl Memory {
             instructions: instructions.into_iter().collect(),
             accumulator: 0,
             index: 0,
+            inputs: VecDeque::new(),
         }
     }
     pub fn from_file<P: AsRef<path::Path>>(path: P) -> Result<Self, Box<dyn error::Error>> {
@@ -39,17 +185,35 @@ impl Memory {
 }
 
 impl FromStr for Memory {
-    type Err = ParseInstructionError;
+    type Err = ParseMemoryError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // https://users.rust-lang.org/t/solved-whats-the-proper-way-to-bubble-up-errors-from-within-closures/13400/2
-        let instructions = s
-            .lines()
-            .map(|line: &str| line.parse::<Instruction>())
-            .collect::<Result<Vec<_>, _>>();
-        match instructions {
-            Ok(i) => Ok(Self::new(i)),
-            Err(e) => Err(e),
+        // Keep going after a bad line, so that we can report all of them
+        let mut instructions = Vec::new();
+        let mut errors = Vec::new();
+        for (number, line) in s.lines().enumerate() {
+            match line.parse::<Instruction>() {
+                Ok(instruction) => instructions.push(instruction),
+                Err(mut e) => {
+                    e.line = number + 1;
+                    errors.push(e)
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(Self::new(instructions)),
+            false => Err(ParseMemoryError { errors }),
+        }
+    }
+}
+
+/// Renders one instruction per line, which parses back to the same instructions.
+/// The accumulator and index aren't part of the text.
+impl fmt::Display for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for instruction in self.instructions.iter() {
+            writeln!(f, "{}", instruction)?;
         }
+        Ok(())
     }
 }
 
@@ -58,6 +222,15 @@ pub enum Status {
     Running(Memory),
     Halted(Memory),
     InvalidIndex(Memory),
+    Faulted(ExecutionFault),
+    /// Stopped at an `in` with nothing in `inputs`
+    AwaitingInput(Memory),
+    /// An `out` just wrote this value
+    Emitted(isize, Memory),
+    /// Used up its step budget, and could carry on
+    OutOfFuel(Memory),
+    /// Ran out of time, and could carry on
+    TimedOut(Memory),
 }
 
 impl From<Memory> for Status {
@@ -66,6 +239,7 @@ impl From<Memory> for Status {
             Halted(memory)
         } else {
             match memory.instructions.get(memory.index as usize) {
+                Some(Input(_)) if memory.inputs.is_empty() => AwaitingInput(memory),
                 Some(_) => Running(memory),
                 None => InvalidIndex(memory),
             }
@@ -73,50 +247,333 @@ impl From<Memory> for Status {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
-pub struct ParseInstructionError;
+/// Why an instruction couldn't be executed.
+/// Each variant holds the memory as it was before the offending instruction.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ExecutionFault {
+    NegativeIndex(Memory),
+    PastEnd(Memory),
+    Overflow(Memory),
+    /// An `in` was executed with nothing in `inputs`
+    NeedsInput(Memory),
+}
+
+/// Which `ExecutionFault`, without the memory.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum FaultKind {
+    NegativeIndex,
+    PastEnd,
+    Overflow,
+    NeedsInput,
+}
+
+impl FaultKind {
+    /// The fault, for `memory` as it was before the offending instruction.
+    pub fn at(self, memory: Memory) -> ExecutionFault {
+        match self {
+            FaultKind::NegativeIndex => ExecutionFault::NegativeIndex(memory),
+            FaultKind::PastEnd => ExecutionFault::PastEnd(memory),
+            FaultKind::Overflow => ExecutionFault::Overflow(memory),
+            FaultKind::NeedsInput => ExecutionFault::NeedsInput(memory),
+        }
+    }
+}
+
+impl ExecutionFault {
+    pub fn kind(&self) -> FaultKind {
+        match self {
+            ExecutionFault::NegativeIndex(_) => FaultKind::NegativeIndex,
+            ExecutionFault::PastEnd(_) => FaultKind::PastEnd,
+            ExecutionFault::Overflow(_) => FaultKind::Overflow,
+            ExecutionFault::NeedsInput(_) => FaultKind::NeedsInput,
+        }
+    }
+    pub fn memory(&self) -> &Memory {
+        match self {
+            ExecutionFault::NegativeIndex(m)
+            | ExecutionFault::PastEnd(m)
+            | ExecutionFault::Overflow(m)
+            | ExecutionFault::NeedsInput(m) => m,
+        }
+    }
+}
+
+impl fmt::Display for ExecutionFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            ExecutionFault::NegativeIndex(_) => "would move to a negative index",
+            ExecutionFault::PastEnd(_) => "would move past the end of the program",
+            ExecutionFault::Overflow(_) => "would overflow",
+            ExecutionFault::NeedsInput(_) => "needs input",
+        };
+        write!(f, "Instruction at index {} {}", self.memory().index, reason)
+    }
+}
+
+impl error::Error for ExecutionFault {}
+
+/// Where an instruction failed to parse, and why.
+/// `line` counts from 1, and `columns` is the byte range of the offending text in that line.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParseInstructionError {
+    pub line: usize,
+    pub columns: ops::Range<usize>,
+    pub reason: ParseReason,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ParseReason {
+    MissingMnemonic,
+    UnknownMnemonic(String),
+    WrongOperandCount { expected: usize, found: usize },
+    BadInteger(num::ParseIntError),
+    BadLabel(String),
+    UnknownLabel(String),
+    DuplicateLabel(String),
+    UnknownRegister(String),
+}
+
 impl fmt::Display for ParseInstructionError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Couldn't parse instruction from {}", "foo")
+        write!(f, "line {}, column {}: ", self.line, self.columns.start + 1)?;
+        match &self.reason {
+            ParseReason::MissingMnemonic => write!(f, "missing mnemonic"),
+            ParseReason::UnknownMnemonic(m) => write!(f, "unknown mnemonic `{}`", m),
+            ParseReason::WrongOperandCount { expected, found } => {
+                write!(f, "expected {} operand(s), found {}", expected, found)
+            }
+            ParseReason::BadInteger(e) => write!(f, "bad integer: {}", e),
+            ParseReason::BadLabel(l) => write!(f, "bad label name `{}`", l),
+            ParseReason::UnknownLabel(l) => write!(f, "unknown label `{}`", l),
+            ParseReason::DuplicateLabel(l) => write!(f, "label `{}` is already defined", l),
+            ParseReason::UnknownRegister(r) => write!(f, "unknown register `{}`", r),
+        }
     }
 }
 
-impl error::Error for ParseInstructionError {}
+impl error::Error for ParseInstructionError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.reason {
+            ParseReason::BadInteger(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
+/// Every line of a program that failed to parse.
 #[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParseMemoryError {
+    pub errors: Vec<ParseInstructionError>,
+}
+
+impl fmt::Display for ParseMemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Couldn't parse {} line(s)", self.errors.len())?;
+        for e in self.errors.iter() {
+            write!(f, "\n{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ParseMemoryError {}
+
+/// The operand of `in` and `out` is a port number for the host's benefit - the machine ignores it.
+/// `in` replaces the accumulator with the next input, `out` writes the accumulator.
+#[derive(Debug, Eq, PartialEq, Clone, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Instruction {
     Noop(isize),
     Accumulate(isize),
     Jump(isize),
+    Input(isize),
+    Output(isize),
+}
+
+impl Instruction {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Noop(_) => "nop",
+            Accumulate(_) => "acc",
+            Jump(_) => "jmp",
+            Input(_) => "in",
+            Output(_) => "out",
+        }
+    }
+    pub fn operand(&self) -> isize {
+        match *self {
+            Noop(n) | Accumulate(n) | Jump(n) | Input(n) | Output(n) => n,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:+}", self.mnemonic(), self.operand())
+    }
+}
+
+/// Each word in `s`, alongside its byte range.
+pub(crate) fn words(s: &str) -> Vec<(ops::Range<usize>, &str)> {
+    s.split_whitespace()
+        .map(|word| {
+            let start = word.as_ptr() as usize - s.as_ptr() as usize;
+            (start..start + word.len(), word)
+        })
+        .collect()
+}
+
+impl Instruction {
+    /// Build an instruction from the words of a line which ends at byte `end`.
+    /// `operand` decides what each operand means - this is where the assembler resolves labels.
+    pub(crate) fn from_words<F>(
+        words: &[(ops::Range<usize>, &str)],
+        end: usize,
+        operand: F,
+    ) -> Result<Self, ParseInstructionError>
+    where
+        F: Fn(&str) -> Result<isize, ParseReason>,
+    {
+        let error = |columns, reason| ParseInstructionError {
+            line: 1,
+            columns,
+            reason,
+        };
+        let ((columns, mnemonic), operands) = match words.split_first() {
+            Some(split) => split,
+            None => return Err(error(0..end, ParseReason::MissingMnemonic)),
+        };
+        let instruction: fn(isize) -> Instruction = match *mnemonic {
+            "nop" => Noop,
+            "acc" => Accumulate,
+            "jmp" => Jump,
+            "in" => Input,
+            "out" => Output,
+            _ => {
+                return Err(error(
+                    columns.clone(),
+                    ParseReason::UnknownMnemonic(mnemonic.to_string()),
+                ))
+            }
+        };
+        match operands {
+            [(columns, word)] => operand(word)
+                .map(instruction)
+                .map_err(|reason| error(columns.clone(), reason)),
+            _ => Err(error(
+                match (operands.first(), operands.last()) {
+                    (Some((first, _)), Some((last, _))) => first.start..last.end,
+                    _ => columns.end..end,
+                },
+                ParseReason::WrongOperandCount {
+                    expected: 1,
+                    found: operands.len(),
+                },
+            )),
+        }
+    }
 }
 
 impl FromStr for Instruction {
     type Err = ParseInstructionError;
+    /// Errors are reported as if `s` were the first line of a program.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut words = s.split_whitespace();
-        let (instruction, args) = (words.next(), words);
-        let args: Result<Vec<isize>, num::ParseIntError> = args.map(|s| s.parse()).collect();
-        match args {
-            Ok(args) => match instruction {
-                Some("nop") if args.len() == 1 => Ok(Noop(args[0])),
-                Some("acc") if args.len() == 1 => Ok(Accumulate(args[0])),
-                Some("jmp") if args.len() == 1 => Ok(Jump(args[0])),
-                Some(_) | None => Err(ParseInstructionError),
-            },
-            Err(_) => Err(ParseInstructionError),
-        }
+        Self::from_words(&words(s), s.len(), |word| {
+            word.parse().map_err(ParseReason::BadInteger)
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    fn instruction() -> impl Strategy<Value = Instruction> {
+        prop_oneof![
+            any::<isize>().prop_map(Noop),
+            any::<isize>().prop_map(Accumulate),
+            any::<isize>().prop_map(Jump),
+            any::<isize>().prop_map(Input),
+            any::<isize>().prop_map(Output),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn render_then_parse(instructions in prop::collection::vec(instruction(), 0..64)) {
+            let memory = Memory::new(instructions);
+            prop_assert_eq!(memory.to_string().parse::<Memory>(), Ok(memory));
+        }
+    }
+
+    #[test]
+    fn render() {
+        assert_eq!(Noop(0).to_string(), "nop +0");
+        assert_eq!(Accumulate(-99).to_string(), "acc -99");
+        assert_eq!(Jump(4).to_string(), "jmp +4");
+        let program = "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n";
+        assert_eq!(program.parse::<Memory>().unwrap().to_string(), program);
+    }
     #[test]
     fn parse_numargs() {
         assert_eq!(Instruction::from_str("nop +1"), Ok(Instruction::Noop(1)));
         assert_eq!(
             Instruction::from_str("nop +1 +2"),
-            Err(ParseInstructionError)
+            Err(ParseInstructionError {
+                line: 1,
+                columns: 4..9,
+                reason: ParseReason::WrongOperandCount {
+                    expected: 1,
+                    found: 2
+                }
+            })
+        );
+        assert_eq!(
+            Instruction::from_str("jmp"),
+            Err(ParseInstructionError {
+                line: 1,
+                columns: 3..3,
+                reason: ParseReason::WrongOperandCount {
+                    expected: 1,
+                    found: 0
+                }
+            })
+        );
+    }
+    #[test]
+    fn parse_reasons() {
+        assert_eq!(
+            Instruction::from_str("  foo +1"),
+            Err(ParseInstructionError {
+                line: 1,
+                columns: 2..5,
+                reason: ParseReason::UnknownMnemonic(String::from("foo"))
+            })
+        );
+        let e = Instruction::from_str("acc +x").unwrap_err();
+        assert_eq!(e.columns, 4..6);
+        assert_eq!(
+            e.reason,
+            ParseReason::BadInteger("+x".parse::<isize>().unwrap_err())
+        );
+        assert_eq!(
+            e.to_string(),
+            "line 1, column 5: bad integer: invalid digit found in string"
+        );
+        assert_eq!(
+            Instruction::from_str("").unwrap_err().reason,
+            ParseReason::MissingMnemonic
+        );
+    }
+    #[test]
+    fn parse_reports_every_line() {
+        let e = Memory::from_str("nop +0\nfoo +1\nacc +1\njmp x\n").unwrap_err();
+        let lines: Vec<usize> = e.errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![2, 4]);
+        assert_eq!(
+            e.to_string(),
+            "Couldn't parse 2 line(s)\nline 2, column 1: unknown mnemonic `foo`\nline 4, column 5: bad integer: invalid digit found in string"
         );
     }
     #[test]
@@ -141,6 +598,87 @@ mod tests {
         );
     }
     #[test]
+    fn faults() {
+        let memory: Memory = "jmp -1".parse().unwrap();
+        match memory.try_execute_instruction() {
+            Err(ExecutionFault::NegativeIndex(m)) => assert_eq!(m.index, 0),
+            _ => panic!(),
+        }
+        let memory: Memory = "jmp +2".parse().unwrap();
+        match memory.try_execute_instruction() {
+            Err(ExecutionFault::PastEnd(m)) => assert_eq!(m.index, 0),
+            _ => panic!(),
+        }
+        let memory: Memory = "acc +1".parse().unwrap();
+        match memory.try_execute_instruction() {
+            Ok(m) => match m.try_execute_instruction() {
+                Err(ExecutionFault::PastEnd(m)) => assert_eq!(m.index, 1),
+                _ => panic!(),
+            },
+            _ => panic!(),
+        }
+        let mut memory: Memory = "acc +1".parse().unwrap();
+        memory.accumulator = isize::MAX;
+        match memory.step() {
+            Faulted(ExecutionFault::Overflow(m)) => assert_eq!(m.accumulator, isize::MAX),
+            _ => panic!(),
+        }
+    }
+    #[test]
+    fn coroutine() {
+        // Echo each input, followed by one more than it
+        let mut memory: Memory = "in +0\nout +0\nacc +1\nout +0\njmp -4\n".parse().unwrap();
+        let mut outputs = Vec::new();
+        for value in [3, 10].iter() {
+            memory = match memory.run() {
+                AwaitingInput(mut m) => {
+                    m.inputs.push_back(*value);
+                    m
+                }
+                other => panic!("{:?}", other),
+            };
+            loop {
+                match memory.run() {
+                    Status::Emitted(value, m) => {
+                        outputs.push(value);
+                        memory = m
+                    }
+                    AwaitingInput(m) => {
+                        memory = m;
+                        break;
+                    }
+                    other => panic!("{:?}", other),
+                }
+            }
+        }
+        assert_eq!(outputs, vec![3, 4, 10, 11]);
+        match memory.try_execute_instruction() {
+            Err(ExecutionFault::NeedsInput(m)) => assert_eq!(m.index, 0),
+            _ => panic!(),
+        }
+    }
+    #[test]
+    fn budgets() {
+        let forever: Memory = "acc +1\njmp -1\n".parse().unwrap();
+        match forever.clone().run_with_budget(5) {
+            OutOfFuel(m) => assert_eq!((m.accumulator, m.index), (3, 1)),
+            other => panic!("{:?}", other),
+        }
+        match forever.run_with_limits(None, Some(Duration::from_millis(10))) {
+            TimedOut(m) => assert!(m.accumulator > 0),
+            other => panic!("{:?}", other),
+        }
+        // Stopping for any other reason wins over running out
+        let short: Memory = "acc +1\n".parse().unwrap();
+        assert!(matches!(short.clone().run_with_budget(1), Halted(_)));
+        assert!(matches!(
+            short.execute_instruction().run_with_budget(0),
+            Halted(_)
+        ));
+        let output: Memory = "out +0\nacc +1\n".parse().unwrap();
+        assert!(matches!(output.run_with_budget(1), Status::Emitted(0, _)));
+    }
+    #[test]
     fn from_invalid_data() {
         Memory::from_file("foo").expect_err("Expected error reading file!");
         Memory::from_str("foo").expect_err("Expected error reading string!");
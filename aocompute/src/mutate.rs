@@ -0,0 +1,504 @@
+use crate::{observer::Budget, Instruction, Memory, Status};
+use rayon::prelude::*;
+use std::{collections::HashSet, mem};
+
+/// One change to a program.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Edit {
+    /// Replace the instruction at `index`
+    Replace {
+        index: usize,
+        instruction: Instruction,
+    },
+    /// Insert `instruction` before `index`
+    Insert {
+        index: usize,
+        instruction: Instruction,
+    },
+    Delete {
+        index: usize,
+    },
+}
+
+impl Edit {
+    /// Where this falls in the program: gaps between instructions are even, and
+    /// instructions are odd.
+    fn position(&self) -> usize {
+        match self {
+            Edit::Insert { index, .. } => 2 * index,
+            Edit::Replace { index, .. } | Edit::Delete { index } => 2 * index + 1,
+        }
+    }
+    pub fn apply(&self, instructions: &mut Vec<Instruction>) {
+        match self {
+            Edit::Replace { index, instruction } => instructions[*index] = instruction.clone(),
+            Edit::Insert { index, instruction } => instructions.insert(*index, instruction.clone()),
+            Edit::Delete { index } => {
+                instructions.remove(*index);
+            }
+        }
+    }
+}
+
+/// An opcode, as the constructor for its instructions, like `Instruction::Noop`.
+pub type Kind = fn(isize) -> Instruction;
+
+/// Which edits count as one step of edit distance. `Rules::default()` allows none.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    /// Opcodes which may replace each other, keeping the operand. Each pair works both ways.
+    pub swaps: Vec<(Kind, Kind)>,
+    /// How far an operand may move either way
+    pub delta: isize,
+    /// Instructions which may be inserted anywhere
+    pub insertions: Vec<Instruction>,
+    pub deletions: bool,
+}
+
+impl Rules {
+    /// Just the day 8 rule: swap a `nop` for a `jmp` or vice versa.
+    pub fn nop_jmp() -> Self {
+        Self::default().with_swap(Instruction::Noop, Instruction::Jump)
+    }
+    pub fn with_swap(mut self, from: Kind, to: Kind) -> Self {
+        self.swaps.push((from, to));
+        self
+    }
+    /// Let operands move by up to `delta` either way.
+    pub fn with_delta(mut self, delta: isize) -> Self {
+        self.delta = delta.saturating_abs();
+        self
+    }
+    pub fn with_insertion(mut self, instruction: Instruction) -> Self {
+        self.insertions.push(instruction);
+        self
+    }
+    pub fn with_deletions(mut self) -> Self {
+        self.deletions = true;
+        self
+    }
+    /// Every instruction one swap or delta away from `instruction`.
+    fn changes(&self, instruction: &Instruction) -> Vec<Instruction> {
+        let mut changes = Vec::new();
+        let is = |instruction: &Instruction, kind: Kind| {
+            mem::discriminant(instruction) == mem::discriminant(&kind(0))
+        };
+        let operand = instruction.operand();
+        for &(a, b) in self.swaps.iter() {
+            for &(from, to) in [(a, b), (b, a)].iter() {
+                if is(instruction, from) && !is(instruction, to) {
+                    changes.push(to(operand));
+                }
+            }
+        }
+        let delta = self.delta.saturating_abs();
+        for delta in (-delta..=delta).filter(|d| *d != 0) {
+            if let Some(moved) = operand.checked_add(delta) {
+                let mut instruction = instruction.clone();
+                match &mut instruction {
+                    Instruction::Noop(n)
+                    | Instruction::Accumulate(n)
+                    | Instruction::Jump(n)
+                    | Instruction::Input(n)
+                    | Instruction::Output(n) => *n = moved,
+                }
+                changes.push(instruction);
+            }
+        }
+        changes
+    }
+    /// Every instruction which `k` or fewer edits make from `starts`, each with the fewest
+    /// edits it takes, nearest first. Each start comes with the edits it took to get there.
+    fn reachable(&self, starts: Vec<(Instruction, usize)>, k: usize) -> Vec<(Instruction, usize)> {
+        let mut seen: HashSet<Instruction> = starts.iter().map(|(i, _)| i.clone()).collect();
+        let mut reached = starts;
+        let mut next = 0;
+        while let Some((instruction, cost)) = reached.get(next).cloned() {
+            next += 1;
+            if cost >= k {
+                continue;
+            }
+            for changed in self.changes(&instruction) {
+                if seen.insert(changed.clone()) {
+                    reached.push((changed, cost + 1));
+                }
+            }
+        }
+        reached
+    }
+    /// Every single edit these rules allow to `instructions`, in program order, with
+    /// insertions before the instruction they go in front of.
+    pub fn edits(&self, instructions: &[Instruction]) -> Vec<Edit> {
+        self.stacked_edits(instructions, 1)
+            .into_iter()
+            .map(|(edit, _)| edit)
+            .collect()
+    }
+    /// As `edits`, but with changes stacked up on each instruction, up to `k` at a time - like
+    /// a swap and a delta, or an insertion and then a delta. Each edit comes with how many
+    /// single edits it counts as.
+    fn stacked_edits(&self, instructions: &[Instruction], k: usize) -> Vec<(Edit, usize)> {
+        let mut edits = Vec::new();
+        for (index, instruction) in instructions.iter().enumerate() {
+            // Skipping the instruction itself
+            for (instruction, cost) in self
+                .reachable(vec![(instruction.clone(), 0)], k)
+                .into_iter()
+                .skip(1)
+            {
+                edits.push((Edit::Replace { index, instruction }, cost));
+            }
+            if self.deletions && k > 0 {
+                edits.push((Edit::Delete { index }, 1));
+            }
+        }
+        let insertions = match k {
+            0 => Vec::new(),
+            _ => {
+                let starts = self.insertions.iter().map(|i| (i.clone(), 1)).collect();
+                self.reachable(starts, k)
+            }
+        };
+        for index in 0..=instructions.len() {
+            for (instruction, cost) in insertions.iter() {
+                let instruction = instruction.clone();
+                edits.push((Edit::Insert { index, instruction }, *cost));
+            }
+        }
+        edits.sort_by_key(|(edit, _)| edit.position());
+        edits
+    }
+}
+
+/// A changed program, and the edits which made it, in an order `Edit::apply` can use.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Mutant {
+    pub instructions: Vec<Instruction>,
+    pub edits: Vec<Edit>,
+}
+
+/// Lazily lists programs made by edits to `instructions`, fewest edits first, up to `k`.
+/// Any number of instructions can be inserted between two, and each instruction can be
+/// replaced or deleted - where replacing it with something two swaps or deltas away counts as
+/// two edits, and so on.
+/// A few different sets of edits can still make the same program - like deleting either of two
+/// identical instructions - or even the original program.
+#[derive(Debug, Clone)]
+pub struct Mutants<'i> {
+    instructions: &'i [Instruction],
+    /// Every edit, in program order, and how many single edits each counts as
+    edits: Vec<(Edit, usize)>,
+    k: usize,
+    /// How many edits the current sets add up to
+    target: usize,
+    /// The current set of edits, as indices into `edits`
+    chosen: Vec<usize>,
+    /// How many edits `chosen` adds up to
+    cost: usize,
+    fresh: bool,
+}
+
+pub fn mutants<'i>(instructions: &'i [Instruction], rules: &Rules, k: usize) -> Mutants<'i> {
+    Mutants {
+        instructions,
+        edits: rules.stacked_edits(instructions, k),
+        k,
+        target: 1,
+        chosen: Vec::new(),
+        cost: 0,
+        fresh: true,
+    }
+}
+
+impl Mutants<'_> {
+    /// The first edit which may follow `previous` - more insertions in the same place, or
+    /// anything later in the program.
+    fn after(&self, previous: Option<&usize>) -> usize {
+        let previous = match previous {
+            Some(&previous) => &self.edits[previous].0,
+            None => return 0,
+        };
+        let position = match previous {
+            Edit::Insert { .. } => previous.position(),
+            _ => previous.position() + 1,
+        };
+        self.edits.partition_point(|(e, _)| e.position() < position)
+    }
+    /// The first edit from `from` on which still fits in the target.
+    fn fitting(&self, from: usize) -> Option<usize> {
+        (from..self.edits.len()).find(|e| self.cost + self.edits[*e].1 <= self.target)
+    }
+    fn push(&mut self, edit: usize) {
+        self.cost += self.edits[edit].1;
+        self.chosen.push(edit);
+    }
+    /// Move `chosen` on to the next set of edits adding up to `target`, if there is one.
+    fn advance(&mut self) -> bool {
+        loop {
+            match self.fresh {
+                true => self.fresh = false,
+                false => {
+                    let last = match self.chosen.pop() {
+                        Some(last) => last,
+                        None => return false,
+                    };
+                    self.cost -= self.edits[last].1;
+                    match self.fitting(last + 1) {
+                        Some(next) => self.push(next),
+                        None => continue,
+                    }
+                }
+            }
+            while self.cost < self.target {
+                match self.fitting(self.after(self.chosen.last())) {
+                    Some(next) => self.push(next),
+                    None => break,
+                }
+            }
+            if self.cost == self.target {
+                return true;
+            }
+        }
+    }
+}
+
+impl Iterator for Mutants<'_> {
+    type Item = Mutant;
+    fn next(&mut self) -> Option<Mutant> {
+        while self.target <= self.k {
+            if self.advance() {
+                // Last first, so that earlier indices still mean the same thing
+                let edits: Vec<Edit> = self
+                    .chosen
+                    .iter()
+                    .rev()
+                    .map(|e| self.edits[*e].0.clone())
+                    .collect();
+                let mut instructions = self.instructions.to_vec();
+                for edit in edits.iter() {
+                    edit.apply(&mut instructions);
+                }
+                return Some(Mutant {
+                    instructions,
+                    edits,
+                });
+            }
+            self.target += 1;
+            self.chosen.clear();
+            self.cost = 0;
+            self.fresh = true;
+        }
+        None
+    }
+}
+
+/// Every distinct program (other than `instructions` itself) from `mutants`, fewest edits first.
+/// Keeps every program seen, so for large searches use `search` or `mutants` directly.
+pub fn within(instructions: &[Instruction], rules: &Rules, k: usize) -> Vec<Mutant> {
+    let mut seen = HashSet::new();
+    seen.insert(instructions.to_vec());
+    mutants(instructions, rules, k)
+        .filter(|mutant| seen.insert(mutant.instructions.clone()))
+        .collect()
+}
+
+/// Run to a final status, carrying on past outputs, with `OutOfFuel` after `max_steps`.
+fn finish(memory: Memory, max_steps: usize) -> Status {
+    let mut budget = Budget(max_steps);
+    let mut memory = memory;
+    loop {
+        match memory.run_observed(&mut budget) {
+            Status::Emitted(_, m) => memory = m,
+            Status::Running(m) => return Status::OutOfFuel(m),
+            status => return status,
+        }
+    }
+}
+
+/// Run the distinct `mutants` of `memory` in parallel, for at most `max_steps` each, and return
+/// one whose final status satisfies `accept` - not necessarily the nearest.
+/// Mutants are made as they're needed, and stop being made as soon as one is found, but every
+/// program made is kept to skip duplicates.
+/// Mutants start from `memory`'s accumulator, index and inputs.
+pub fn search<P>(
+    memory: &Memory,
+    rules: &Rules,
+    k: usize,
+    max_steps: usize,
+    accept: P,
+) -> Option<(Mutant, Status)>
+where
+    P: Fn(&Status) -> bool + Sync,
+{
+    let mut seen = HashSet::new();
+    seen.insert(memory.instructions.clone());
+    mutants(&memory.instructions, rules, k)
+        .filter(|mutant| seen.insert(mutant.instructions.clone()))
+        .par_bridge()
+        .find_map_any(|mutant| {
+            let mutated = Memory {
+                instructions: mutant.instructions.clone(),
+                accumulator: memory.accumulator,
+                index: memory.index,
+                inputs: memory.inputs.clone(),
+            };
+            let status = finish(mutated, max_steps);
+            match accept(&status) {
+                true => Some((mutant, status)),
+                false => None,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n";
+
+    fn halted(status: &Status) -> bool {
+        matches!(status, Status::Halted(_))
+    }
+
+    #[test]
+    fn example() {
+        let memory: Memory = EXAMPLE.parse().unwrap();
+        // Instructions 0, 2, 4 and 7 can each be swapped
+        assert_eq!(within(&memory.instructions, &Rules::nop_jmp(), 1).len(), 4);
+        let (mutant, status) = search(&memory, &Rules::nop_jmp(), 1, 1000, halted).unwrap();
+        assert_eq!(
+            mutant.edits,
+            vec![Edit::Replace {
+                index: 7,
+                instruction: Instruction::Noop(-4)
+            }]
+        );
+        match status {
+            Status::Halted(m) => assert_eq!(m.accumulator, 8),
+            _ => panic!(),
+        }
+        assert!(search(&memory, &Rules::default(), 3, 1000, halted).is_none());
+    }
+    #[test]
+    fn duplicates() {
+        let instructions = vec![Instruction::Noop(0)];
+        // Each instruction only changes once, so it can't be swapped back
+        assert_eq!(mutants(&instructions, &Rules::nop_jmp(), 2).count(), 1);
+        // Two deletions from `nop; nop` in either order make the same empty program
+        let instructions = vec![Instruction::Noop(0), Instruction::Noop(0)];
+        let mutants = within(&instructions, &Rules::default().with_deletions(), 2);
+        let programs: Vec<_> = mutants.into_iter().map(|m| m.instructions).collect();
+        assert_eq!(programs, vec![vec![Instruction::Noop(0)], vec![]]);
+    }
+    #[test]
+    fn rules() {
+        let instructions = vec![Instruction::Accumulate(isize::MAX), Instruction::Jump(0)];
+        let rules = Rules::default()
+            .with_delta(2)
+            .with_swap(Instruction::Accumulate, Instruction::Noop)
+            .with_insertion(Instruction::Output(0));
+        let edits = rules.edits(&instructions);
+        // One swap, 2 + 4 deltas, 3 insertion points
+        assert_eq!(edits.len(), 1 + 6 + 3);
+        let mutants = within(&instructions, &rules, 1);
+        assert_eq!(mutants.len(), edits.len());
+        assert!(mutants
+            .iter()
+            .any(|m| m.instructions == vec![Instruction::Noop(isize::MAX), Instruction::Jump(0)]));
+    }
+    #[test]
+    fn deeper() {
+        // Needs the `jmp +0` fixed, then the extra `acc` removed
+        let memory: Memory = "acc +1\njmp +0\nacc +5\n".parse().unwrap();
+        let rules = Rules::nop_jmp().with_deletions();
+        let accumulator_is_1 = |status: &Status| match status {
+            Status::Halted(m) => m.accumulator == 1,
+            _ => false,
+        };
+        assert!(search(&memory, &rules, 1, 100, accumulator_is_1).is_none());
+        let (mutant, _) = search(&memory, &rules, 2, 100, accumulator_is_1).unwrap();
+        assert_eq!(mutant.edits.len(), 2);
+        let status = finish(memory, 100);
+        assert!(matches!(status, Status::OutOfFuel(_)));
+    }
+    #[test]
+    fn stacked() {
+        let rules = Rules::nop_jmp().with_delta(1);
+        let instructions = vec![Instruction::Noop(0)];
+        let programs: Vec<_> = mutants(&instructions, &rules, 2)
+            .map(|m| m.instructions)
+            .collect();
+        // A swap or a delta, then a swap and a delta, or two deltas
+        assert_eq!(
+            programs,
+            vec![
+                vec![Instruction::Jump(0)],
+                vec![Instruction::Noop(-1)],
+                vec![Instruction::Noop(1)],
+                vec![Instruction::Jump(-1)],
+                vec![Instruction::Jump(1)],
+                vec![Instruction::Noop(-2)],
+                vec![Instruction::Noop(2)],
+            ]
+        );
+        // Insert, then change what was inserted
+        let rules = Rules::default()
+            .with_delta(1)
+            .with_insertion(Instruction::Noop(0));
+        let programs: Vec<_> = mutants(&[], &rules, 2).map(|m| m.instructions).collect();
+        assert_eq!(
+            programs,
+            vec![
+                vec![Instruction::Noop(0)],
+                vec![Instruction::Noop(0); 2],
+                vec![Instruction::Noop(-1)],
+                vec![Instruction::Noop(1)],
+            ]
+        );
+        // Only the size of the delta matters
+        assert_eq!(Rules::default().with_delta(-2).delta, 2);
+        assert_eq!(Rules::default().with_delta(isize::MIN).delta, isize::MAX);
+    }
+    #[test]
+    fn search_skips_duplicates() {
+        // Deleting either `nop` makes the same program, which only runs once
+        let memory: Memory = "nop +0\nnop +0\n".parse().unwrap();
+        let runs = std::sync::atomic::AtomicUsize::new(0);
+        let rules = Rules::default().with_deletions();
+        let found = search(&memory, &rules, 2, 10, |_| {
+            runs.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            false
+        });
+        assert!(found.is_none());
+        assert_eq!(runs.into_inner(), 2);
+    }
+    #[test]
+    fn insertions_in_order() {
+        let rules = Rules::default()
+            .with_insertion(Instruction::Noop(0))
+            .with_insertion(Instruction::Output(0));
+        let programs: Vec<_> = mutants(&[Instruction::Jump(1)], &rules, 2)
+            .map(|m| m.instructions)
+            .collect();
+        // 2 single insertions in each of 2 places, then 4 pairs in each place and 4 split
+        assert_eq!(programs.len(), 4 + 4 + 4 + 4);
+        let (nop, out, jmp) = (
+            Instruction::Noop(0),
+            Instruction::Output(0),
+            Instruction::Jump(1),
+        );
+        assert!(programs.contains(&vec![out.clone(), nop.clone(), jmp.clone()]));
+        assert!(programs.contains(&vec![nop, jmp, out]));
+    }
+    #[test]
+    fn budget_runs_out_on_an_output() {
+        let memory: Memory = "out +0\njmp -1\n".parse().unwrap();
+        let (_, status) = search(&memory, &Rules::nop_jmp(), 1, 1, |_| true).unwrap();
+        match status {
+            Status::OutOfFuel(m) => assert_eq!(m.index, 1),
+            _ => panic!(),
+        }
+        assert!(matches!(finish(memory, 0), Status::OutOfFuel(_)));
+    }
+}
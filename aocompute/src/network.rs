@@ -0,0 +1,500 @@
+use crate::{Memory, Status};
+use std::{error, fmt, mem, num, str::FromStr};
+
+/// Where a machine's `out` values go.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Route {
+    /// Collected in `Network::outputs`
+    Host,
+    /// Copied onto the inputs of each of these machines
+    Links(Vec<usize>),
+    /// Read in pairs of `(address, value)`. The value goes to the machine with that index,
+    /// or the NAT if it has that address, or else the host.
+    Packets,
+}
+
+/// The most machines a `machines` directive may ask for, so that a bad count fails to parse
+/// instead of allocating them all.
+pub const MAX_MACHINES: usize = 1 << 16;
+
+/// How machines are wired together, written one directive per line:
+/// ```text
+/// machines 5          ; must come first
+/// pipeline 0 1 2      ; 0 -> 1 -> 2
+/// ring 2 3 4          ; 2 -> 3 -> 4 -> 2
+/// link 4 0            ; 4 -> 0
+/// packets 0 1 2       ; these send addressed packets instead
+/// nat 255 0           ; packets to 255 are held until the network is idle, then sent to 0
+/// input 0 9 5         ; queue 9 then 5 as inputs for 0
+/// quantum 100         ; instructions each machine runs per turn
+/// ```
+/// Anything after a `;` is a comment.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Topology {
+    pub routes: Vec<Route>,
+    pub inputs: Vec<Vec<isize>>,
+    /// The NAT's address, and which machine it wakes
+    pub nat: Option<(isize, usize)>,
+    pub quantum: usize,
+}
+
+impl Topology {
+    /// `machines` unconnected machines, each sending to the host.
+    pub fn new(machines: usize) -> Self {
+        Self {
+            routes: vec![Route::Host; machines],
+            inputs: vec![Vec::new(); machines],
+            nat: None,
+            quantum: 100,
+        }
+    }
+    pub fn machines(&self) -> usize {
+        self.routes.len()
+    }
+    /// Send `from`'s outputs to `to`, as well as anywhere they already go.
+    pub fn link(&mut self, from: usize, to: usize) -> Result<(), TopologyReason> {
+        if let Some(&machine) = [from, to].iter().find(|&&m| m >= self.machines()) {
+            return Err(TopologyReason::NoSuchMachine(machine as isize));
+        }
+        match &mut self.routes[from] {
+            route @ Route::Host => *route = Route::Links(vec![to]),
+            Route::Links(links) => links.push(to),
+            Route::Packets => return Err(TopologyReason::ConflictingRoutes(from)),
+        }
+        Ok(())
+    }
+    fn directive(&mut self, name: &str, operands: &[usize]) -> Result<(), TopologyReason> {
+        let machines = self.machines();
+        if let Some(&machine) = operands.iter().find(|&&m| m >= machines) {
+            return Err(TopologyReason::NoSuchMachine(machine as isize));
+        }
+        match (name, operands) {
+            ("link", &[from, to]) => self.link(from, to)?,
+            ("pipeline", machines) | ("ring", machines) if machines.len() >= 2 => {
+                for pair in machines.windows(2) {
+                    self.link(pair[0], pair[1])?
+                }
+                if name == "ring" {
+                    self.link(machines[machines.len() - 1], machines[0])?
+                }
+            }
+            ("packets", machines) if !machines.is_empty() => {
+                for &machine in machines {
+                    match self.routes[machine] {
+                        Route::Links(_) => return Err(TopologyReason::ConflictingRoutes(machine)),
+                        _ => self.routes[machine] = Route::Packets,
+                    }
+                }
+            }
+            _ => return Err(TopologyReason::WrongOperandCount),
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Topology {
+    type Err = ParseTopologyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut topology: Option<Topology> = None;
+        for (number, line) in s.lines().enumerate() {
+            let error = |reason| ParseTopologyError {
+                line: number + 1,
+                reason,
+            };
+            let mut words = line.split(';').next().unwrap_or("").split_whitespace();
+            let name = match words.next() {
+                Some(name) => name,
+                None => continue,
+            };
+            let operands = words
+                .map(str::parse)
+                .collect::<Result<Vec<isize>, _>>()
+                .map_err(|e| error(TopologyReason::BadInteger(e)))?;
+            let t = match (name, topology.as_mut(), &operands[..]) {
+                ("machines", None, &[n]) if n >= 0 && n as usize <= MAX_MACHINES => {
+                    topology = Some(Topology::new(n as usize));
+                    continue;
+                }
+                ("machines", None, &[n]) if n >= 0 => {
+                    return Err(error(TopologyReason::TooManyMachines(n as usize)))
+                }
+                ("machines", Some(_), _) => return Err(error(TopologyReason::DuplicateMachines)),
+                ("machines", None, _) => return Err(error(TopologyReason::WrongOperandCount)),
+                (_, None, _) => return Err(error(TopologyReason::MissingMachines)),
+                (_, Some(t), _) => t,
+            };
+            match (name, &operands[..]) {
+                ("nat", &[address, machine]) => match machine {
+                    m if m >= 0 && (m as usize) < t.machines() => {
+                        t.nat = Some((address, m as usize))
+                    }
+                    m => return Err(error(TopologyReason::NoSuchMachine(m))),
+                },
+                ("input", &[machine, ref values @ ..]) => match machine {
+                    m if m >= 0 && (m as usize) < t.machines() => {
+                        t.inputs[m as usize].extend_from_slice(values)
+                    }
+                    m => return Err(error(TopologyReason::NoSuchMachine(m))),
+                },
+                ("quantum", &[n]) if n > 0 => t.quantum = n as usize,
+                ("nat", _) | ("input", _) | ("quantum", _) => {
+                    return Err(error(TopologyReason::WrongOperandCount))
+                }
+                ("link", _) | ("pipeline", _) | ("ring", _) | ("packets", _) => {
+                    let machines = operands
+                        .iter()
+                        .map(|&m| match m {
+                            m if m >= 0 => Ok(m as usize),
+                            m => Err(TopologyReason::NoSuchMachine(m)),
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(error)?;
+                    t.directive(name, &machines).map_err(error)?
+                }
+                (other, _) => {
+                    return Err(error(TopologyReason::UnknownDirective(other.to_string())))
+                }
+            }
+        }
+        topology.ok_or(ParseTopologyError {
+            line: s.lines().count(),
+            reason: TopologyReason::MissingMachines,
+        })
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParseTopologyError {
+    pub line: usize,
+    pub reason: TopologyReason,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum TopologyReason {
+    MissingMachines,
+    DuplicateMachines,
+    UnknownDirective(String),
+    WrongOperandCount,
+    BadInteger(num::ParseIntError),
+    NoSuchMachine(isize),
+    /// More than `MAX_MACHINES`
+    TooManyMachines(usize),
+    /// A machine can't both send packets and be linked.
+    ConflictingRoutes(usize),
+}
+
+impl fmt::Display for ParseTopologyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.reason {
+            TopologyReason::MissingMachines => write!(f, "expected `machines <count>` first"),
+            TopologyReason::DuplicateMachines => write!(f, "`machines` given more than once"),
+            TopologyReason::UnknownDirective(d) => write!(f, "unknown directive `{}`", d),
+            TopologyReason::WrongOperandCount => write!(f, "wrong number of operands"),
+            TopologyReason::BadInteger(e) => write!(f, "{}", e),
+            TopologyReason::NoSuchMachine(m) => write!(f, "no machine {}", m),
+            TopologyReason::TooManyMachines(n) => {
+                write!(
+                    f,
+                    "{} machines is more than the {} allowed",
+                    n, MAX_MACHINES
+                )
+            }
+            TopologyReason::ConflictingRoutes(m) => {
+                write!(f, "machine {} can't both send packets and be linked", m)
+            }
+        }
+    }
+}
+
+impl error::Error for ParseTopologyError {}
+
+/// Why `Network::run` returned.
+#[derive(Debug)]
+pub enum NetworkStatus {
+    /// Every machine has halted
+    Halted,
+    /// Every machine is waiting for input and nothing is in flight - the host may add some
+    Idle,
+    /// Some machines have halted, and the rest are waiting for input which will never come
+    Deadlocked,
+    /// A machine stopped with an invalid index or faulted
+    Crashed(usize, Status),
+    /// Still going after the requested number of rounds
+    OutOfRounds,
+}
+
+/// Several machines, run in turn for `quantum` instructions each, passing values between them.
+/// The order of turns is fixed, so a run is always the same.
+#[derive(Debug, Clone)]
+pub struct Network {
+    pub machines: Vec<Memory>,
+    pub topology: Topology,
+    /// Values sent to the host, with the machine that sent them
+    pub outputs: Vec<(usize, isize)>,
+    /// The last value each machine wrote, wherever it went
+    pub last_output: Vec<Option<isize>>,
+    /// Everything the NAT has sent, in order
+    pub nat_deliveries: Vec<isize>,
+    halted: Vec<bool>,
+    /// Addresses of packets whose value hasn't been written yet
+    addresses: Vec<Option<isize>>,
+    nat_packet: Option<isize>,
+}
+
+impl Network {
+    /// # Panics
+    /// If the topology describes a different number of machines.
+    pub fn new(topology: Topology, mut machines: Vec<Memory>) -> Self {
+        assert_eq!(
+            topology.machines(),
+            machines.len(),
+            "One program per machine"
+        );
+        for (memory, inputs) in machines.iter_mut().zip(topology.inputs.iter()) {
+            memory.inputs.extend(inputs.iter().copied());
+        }
+        let count = machines.len();
+        Self {
+            machines,
+            topology,
+            outputs: Vec::new(),
+            last_output: vec![None; count],
+            nat_deliveries: Vec::new(),
+            halted: vec![false; count],
+            addresses: vec![None; count],
+            nat_packet: None,
+        }
+    }
+    /// Every machine runs its own copy of `memory`.
+    pub fn replicate(topology: Topology, memory: &Memory) -> Self {
+        let machines = vec![memory.clone(); topology.machines()];
+        Self::new(topology, machines)
+    }
+    /// Give every machine a turn, up to `max_rounds` times.
+    /// When no machine can do anything, the NAT (if there is one) gets a turn.
+    pub fn run(&mut self, max_rounds: usize) -> NetworkStatus {
+        for _ in 0..max_rounds {
+            let mut progress = false;
+            for machine in 0..self.machines.len() {
+                match self.turn(machine) {
+                    Ok(stepped) => progress |= stepped,
+                    Err(status) => return NetworkStatus::Crashed(machine, status),
+                }
+            }
+            if progress {
+                continue;
+            }
+            // Nothing ran, so everyone has halted or is waiting on an empty queue
+            match (self.nat_packet.take(), self.topology.nat) {
+                (Some(value), Some((_, target))) => {
+                    self.nat_deliveries.push(value);
+                    self.machines[target].inputs.push_back(value)
+                }
+                _ if self.halted.iter().all(|&h| h) => return NetworkStatus::Halted,
+                _ if self.halted.iter().any(|&h| h) => return NetworkStatus::Deadlocked,
+                _ => return NetworkStatus::Idle,
+            }
+        }
+        NetworkStatus::OutOfRounds
+    }
+    /// Run `machine` until it blocks, stops or uses up its quantum.
+    /// Returns whether it executed anything.
+    fn turn(&mut self, machine: usize) -> Result<bool, Status> {
+        let mut memory = mem::replace(&mut self.machines[machine], Memory::new(Vec::new()));
+        let mut steps = 0;
+        while steps < self.topology.quantum {
+            match memory.step() {
+                Status::Running(m) => memory = m,
+                Status::Emitted(value, m) => {
+                    // Put back while sending, in case it sends to itself
+                    self.machines[machine] = m;
+                    self.send(machine, value);
+                    memory = mem::replace(&mut self.machines[machine], Memory::new(Vec::new()));
+                }
+                Status::AwaitingInput(m) => {
+                    memory = m;
+                    break;
+                }
+                Status::Halted(m) => {
+                    memory = m;
+                    self.halted[machine] = true;
+                    break;
+                }
+                status => {
+                    self.machines[machine] = match &status {
+                        Status::Faulted(fault) => fault.memory().clone(),
+                        Status::InvalidIndex(m) => m.clone(),
+                        _ => unreachable!("Handled above"),
+                    };
+                    return Err(status);
+                }
+            }
+            steps += 1;
+        }
+        self.machines[machine] = memory;
+        Ok(steps > 0)
+    }
+    fn send(&mut self, from: usize, value: isize) {
+        self.last_output[from] = Some(value);
+        match &self.topology.routes[from] {
+            Route::Host => self.outputs.push((from, value)),
+            Route::Links(links) => {
+                for &to in links.iter() {
+                    self.machines[to].inputs.push_back(value)
+                }
+            }
+            Route::Packets => match self.addresses[from].take() {
+                None => self.addresses[from] = Some(value),
+                Some(address) => match self.topology.nat {
+                    Some((nat, _)) if nat == address => self.nat_packet = Some(value),
+                    _ if address >= 0 && (address as usize) < self.machines.len() => {
+                        self.machines[address as usize].inputs.push_back(value)
+                    }
+                    _ => self.outputs.push((from, value)),
+                },
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assembler::assemble, ExecutionFault};
+    use std::collections::VecDeque;
+
+    /// Reads a value, and writes one more than it.
+    const INCREMENT: &str = "in +0\nacc +1\nout +0\n";
+
+    fn increments(topology: &str) -> Network {
+        Network::replicate(topology.parse().unwrap(), &INCREMENT.parse().unwrap())
+    }
+
+    #[test]
+    fn pipeline() {
+        let mut network = increments("machines 3\npipeline 0 1 2 ; a chain\n\ninput 0 5\n");
+        assert!(matches!(network.run(10), NetworkStatus::Halted));
+        assert_eq!(network.outputs, vec![(2, 8)]);
+    }
+    #[test]
+    fn ring() {
+        let mut network = increments("machines 3\nring 0 1 2\ninput 0 0\n");
+        assert!(matches!(network.run(10), NetworkStatus::Halted));
+        assert_eq!(network.outputs, vec![]);
+        assert_eq!(network.last_output, vec![Some(1), Some(2), Some(3)]);
+        assert_eq!(network.machines[0].inputs, VecDeque::from(vec![3]));
+
+        // Forever passing the value around
+        let forever: Memory = "in +0\nacc +1\nout +0\njmp -3\n".parse().unwrap();
+        let topology: Topology = "machines 3\nring 0 1 2\ninput 0 0\nquantum 1\n"
+            .parse()
+            .unwrap();
+        let mut network = Network::replicate(topology, &forever);
+        assert!(matches!(network.run(100), NetworkStatus::OutOfRounds));
+        let mut again = network.clone();
+        again.run(100);
+        network.run(100);
+        assert_eq!(network.last_output, again.last_output);
+        assert!(network.last_output[2].unwrap() > 30);
+    }
+    #[test]
+    fn deadlock_and_idle() {
+        let topology: Topology = "machines 2\nlink 0 1\n".parse().unwrap();
+        let programs = vec![
+            "acc +1\nout +0\n".parse().unwrap(),
+            "in +0\nin +0\n".parse().unwrap(),
+        ];
+        let mut network = Network::new(topology, programs);
+        assert!(matches!(network.run(10), NetworkStatus::Deadlocked));
+        assert_eq!(network.machines[1].index, 1);
+
+        let mut network = increments("machines 2\nring 0 1\n");
+        assert!(matches!(network.run(10), NetworkStatus::Idle));
+        network.machines[1].inputs.push_back(10);
+        assert!(matches!(network.run(10), NetworkStatus::Halted));
+        assert_eq!(network.last_output, vec![Some(12), Some(11)]);
+    }
+    #[test]
+    fn packets_and_nat() {
+        let topology: Topology = "machines 3\npackets 0 1 2\nnat 9 0\n".parse().unwrap();
+        let programs = vec![
+            // Send 7 to machine 2, then wait
+            assemble("acc +2\nout +0 ; address\nacc +5\nout +0 ; value\nin +0\n").unwrap(),
+            assemble("in +0 ; never hears anything\n").unwrap(),
+            // Once we hear anything, send 42 to the NAT
+            assemble("in +0\nacc -7\nacc +9\nout +0\nacc -9\nacc +42\nout +0\n").unwrap(),
+        ];
+        let mut network = Network::new(topology, programs);
+        assert!(matches!(network.run(10), NetworkStatus::Deadlocked));
+        assert_eq!(network.nat_deliveries, vec![42]);
+        assert_eq!(network.machines[0].accumulator, 42);
+        assert!(network.outputs.is_empty());
+    }
+    #[test]
+    fn crash() {
+        let topology: Topology = "machines 2".parse().unwrap();
+        let programs = vec![INCREMENT.parse().unwrap(), "jmp -1\n".parse().unwrap()];
+        let mut network = Network::new(topology, programs);
+        match network.run(10) {
+            NetworkStatus::Crashed(1, Status::Faulted(ExecutionFault::NegativeIndex(_))) => (),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(network.machines[1].instructions.len(), 1);
+    }
+    #[test]
+    fn sending_to_itself() {
+        let memory = "in +0\nacc +1\nout +0\nin +0\nout +0\n".parse().unwrap();
+        let topology = "machines 1\nlink 0 0\ninput 0 1\n".parse().unwrap();
+        let mut network = Network::replicate(topology, &memory);
+        assert!(matches!(network.run(10), NetworkStatus::Halted));
+        assert_eq!(
+            network.machines[0].inputs,
+            vec![2].into_iter().collect::<VecDeque<_>>()
+        );
+
+        // An address, then a value, which is read back
+        let memory = "out +0\nacc +7\nout +0\nacc -7\nin +0\n".parse().unwrap();
+        let mut network = Network::replicate("machines 1\npackets 0\n".parse().unwrap(), &memory);
+        assert!(matches!(network.run(10), NetworkStatus::Halted));
+        assert_eq!(network.machines[0].accumulator, 7);
+        assert!(network.outputs.is_empty());
+    }
+    #[test]
+    fn parse_errors() {
+        let error = |s: &str| s.parse::<Topology>().unwrap_err();
+        assert_eq!(error("link 0 1\n").reason, TopologyReason::MissingMachines);
+        assert_eq!(error("").reason, TopologyReason::MissingMachines);
+        assert_eq!(
+            error("machines 2\n\nlink 0 2\n"),
+            ParseTopologyError {
+                line: 3,
+                reason: TopologyReason::NoSuchMachine(2)
+            }
+        );
+        assert_eq!(
+            error("machines 2\npipeline 0 1\npackets 0\n").reason,
+            TopologyReason::ConflictingRoutes(0)
+        );
+        assert_eq!(
+            error("machines 2\nring 0\n").reason,
+            TopologyReason::WrongOperandCount
+        );
+        assert_eq!(
+            error("machines 2\nbus 0 1\n").to_string(),
+            "line 2: unknown directive `bus`"
+        );
+        assert!(matches!(
+            error("machines two\n").reason,
+            TopologyReason::BadInteger(_)
+        ));
+        assert_eq!(
+            error("machines 9223372036854775807\n").reason,
+            TopologyReason::TooManyMachines(isize::MAX as usize)
+        );
+        assert_eq!(
+            Topology::new(2).link(0, 2),
+            Err(TopologyReason::NoSuchMachine(2))
+        );
+    }
+}
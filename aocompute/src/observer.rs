@@ -0,0 +1,244 @@
+use crate::{ExecutionFault, Memory};
+use std::collections::{BTreeSet, HashSet};
+
+/// Whether an observer wants `Memory::run_observed` to carry on.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Control {
+    Continue,
+    Stop,
+}
+
+impl Control {
+    fn or(self, other: Self) -> Self {
+        match (self, other) {
+            (Control::Continue, Control::Continue) => Control::Continue,
+            _ => Control::Stop,
+        }
+    }
+}
+
+/// Callbacks from `Memory::run_observed`, for instrumenting a run without wrapping `Memory`.
+/// Every callback does nothing by default.
+pub trait VmObserver {
+    /// Before executing the instruction at `memory.index`. `Stop` leaves it unexecuted.
+    fn before_step(&mut self, _memory: &Memory) -> Control {
+        Control::Continue
+    }
+    /// After executing the instruction at `index`, which had `accumulator` before it ran.
+    /// `Stop` ends the run before the next instruction. An `out` ends the run with `Emitted`
+    /// anyway, and the next run won't know about the `Stop`, so an observer which must stop
+    /// straight after an `out` should do it in `before_step`.
+    fn after_step(&mut self, _index: isize, _accumulator: isize, _memory: &Memory) -> Control {
+        Control::Continue
+    }
+    fn on_halt(&mut self, _memory: &Memory) {}
+    fn on_fault(&mut self, _fault: &ExecutionFault) {}
+}
+
+/// Observes nothing, for a plain `run`.
+impl VmObserver for () {}
+
+/// Lends an observer to a run without giving it up, e.g. as half of a pair.
+impl<O: VmObserver + ?Sized> VmObserver for &mut O {
+    fn before_step(&mut self, memory: &Memory) -> Control {
+        (**self).before_step(memory)
+    }
+    fn after_step(&mut self, index: isize, accumulator: isize, memory: &Memory) -> Control {
+        (**self).after_step(index, accumulator, memory)
+    }
+    fn on_halt(&mut self, memory: &Memory) {
+        (**self).on_halt(memory)
+    }
+    fn on_fault(&mut self, fault: &ExecutionFault) {
+        (**self).on_fault(fault)
+    }
+}
+
+/// Both observers see everything, and either can stop the run.
+impl<A: VmObserver, B: VmObserver> VmObserver for (A, B) {
+    fn before_step(&mut self, memory: &Memory) -> Control {
+        let (a, b) = (self.0.before_step(memory), self.1.before_step(memory));
+        a.or(b)
+    }
+    fn after_step(&mut self, index: isize, accumulator: isize, memory: &Memory) -> Control {
+        let a = self.0.after_step(index, accumulator, memory);
+        let b = self.1.after_step(index, accumulator, memory);
+        a.or(b)
+    }
+    fn on_halt(&mut self, memory: &Memory) {
+        self.0.on_halt(memory);
+        self.1.on_halt(memory);
+    }
+    fn on_fault(&mut self, fault: &ExecutionFault) {
+        self.0.on_fault(fault);
+        self.1.on_fault(fault);
+    }
+}
+
+/// Stops before any instruction would move to an index that's been moved to before.
+/// This is the whole of what `ImmutableMemory::run` adds to `Memory`.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Visited {
+    pub indices: HashSet<isize>,
+}
+
+impl VmObserver for Visited {
+    fn before_step(&mut self, memory: &Memory) -> Control {
+        match memory.lookahead() {
+            Ok((_, index)) if self.indices.contains(&index) => Control::Stop,
+            _ => Control::Continue,
+        }
+    }
+    fn after_step(&mut self, _index: isize, _accumulator: isize, memory: &Memory) -> Control {
+        self.indices.insert(memory.index);
+        Control::Continue
+    }
+}
+
+/// Stops before executing the instruction at any of `indices`, except straight after stopping
+/// there, so that a run can be resumed from a breakpoint.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct Breakpoints {
+    pub indices: BTreeSet<isize>,
+    stopped_at: Option<isize>,
+}
+
+impl Breakpoints {
+    pub fn new<I: IntoIterator<Item = isize>>(indices: I) -> Self {
+        Self {
+            indices: indices.into_iter().collect(),
+            stopped_at: None,
+        }
+    }
+}
+
+impl VmObserver for Breakpoints {
+    fn before_step(&mut self, memory: &Memory) -> Control {
+        let index = memory.index;
+        match self.indices.contains(&index) && self.stopped_at != Some(index) {
+            true => {
+                self.stopped_at = Some(index);
+                Control::Stop
+            }
+            false => {
+                self.stopped_at = None;
+                Control::Continue
+            }
+        }
+    }
+}
+
+/// Stops a run after a number of steps.
+pub(crate) struct Budget(pub(crate) usize);
+
+impl VmObserver for Budget {
+    // In `before_step`, so that running out straight after an `out` isn't missed
+    fn before_step(&mut self, _memory: &Memory) -> Control {
+        match self.0 {
+            0 => Control::Stop,
+            _ => Control::Continue,
+        }
+    }
+    fn after_step(&mut self, _index: isize, _accumulator: isize, _memory: &Memory) -> Control {
+        self.0 = self.0.saturating_sub(1);
+        Control::Continue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{profile::Profile, Status};
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n";
+
+    /// Counts the callbacks it gets.
+    #[derive(Debug, Default)]
+    struct Counter {
+        before: usize,
+        after: usize,
+        halts: usize,
+        faults: usize,
+    }
+
+    impl VmObserver for Counter {
+        fn before_step(&mut self, _memory: &Memory) -> Control {
+            self.before += 1;
+            Control::Continue
+        }
+        fn after_step(&mut self, _index: isize, _accumulator: isize, _memory: &Memory) -> Control {
+            self.after += 1;
+            Control::Continue
+        }
+        fn on_halt(&mut self, _memory: &Memory) {
+            self.halts += 1;
+        }
+        fn on_fault(&mut self, _fault: &ExecutionFault) {
+            self.faults += 1;
+        }
+    }
+
+    #[test]
+    fn callbacks() {
+        let mut counter = Counter::default();
+        let memory: Memory = "acc +1\nnop +0\nacc +1\n".parse().unwrap();
+        match memory.run_observed(&mut counter) {
+            Status::Halted(m) => assert_eq!(m.accumulator, 2),
+            _ => panic!(),
+        }
+        assert_eq!((counter.before, counter.after, counter.halts), (3, 3, 1));
+        let memory: Memory = "acc +1\njmp -2\n".parse().unwrap();
+        match memory.run_observed(&mut counter) {
+            Status::Faulted(f) => assert_eq!(f.memory().index, 1),
+            _ => panic!(),
+        }
+        assert_eq!((counter.before, counter.after, counter.faults), (5, 4, 1));
+    }
+    #[test]
+    fn revisits() {
+        let mut visited = Visited::default();
+        let memory: Memory = EXAMPLE.parse().unwrap();
+        match memory.run_observed(&mut visited) {
+            Status::Running(m) => assert_eq!((m.index, m.accumulator), (4, 5)),
+            _ => panic!(),
+        }
+        assert_eq!(visited.indices.len(), 6);
+    }
+    #[test]
+    fn breakpoints_and_profile() {
+        let memory: Memory = EXAMPLE.parse().unwrap();
+        let breakpoints = Breakpoints::new(vec![3]);
+        let mut observer = (Profile::new(&memory.instructions), breakpoints);
+        let memory = match memory.run_observed(&mut observer) {
+            Status::Running(m) => m,
+            _ => panic!(),
+        };
+        assert_eq!((memory.index, memory.accumulator), (3, 2));
+        assert_eq!(observer.0.steps(), 5);
+        // Resuming doesn't stop at the same breakpoint straight away
+        match memory.run_observed(&mut observer) {
+            Status::Running(m) => assert_eq!((m.index, m.accumulator), (3, 7)),
+            _ => panic!(),
+        }
+        assert_eq!(observer.0.hits[3], 1);
+    }
+    #[test]
+    fn breakpoint_after_output() {
+        let memory: Memory = "nop +0\nout +0\nacc +1\nacc +1\n".parse().unwrap();
+        let mut breakpoints = Breakpoints::new(vec![2]);
+        let memory = match memory.run_observed(&mut breakpoints) {
+            Status::Emitted(0, m) => m,
+            _ => panic!(),
+        };
+        let memory = match memory.run_observed(&mut breakpoints) {
+            Status::Running(m) => m,
+            _ => panic!(),
+        };
+        assert_eq!((memory.index, memory.accumulator), (2, 0));
+        match memory.run_observed(&mut breakpoints) {
+            Status::Halted(m) => assert_eq!(m.accumulator, 2),
+            _ => panic!(),
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use crate::Instruction::{self, *};
+use std::{collections::HashSet, fmt};
+
+/// The result of `optimize`, and how much smaller it is.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Optimization {
+    pub instructions: Vec<Instruction>,
+    pub before: usize,
+    pub after: usize,
+}
+
+impl fmt::Display for Optimization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {} instructions", self.before, self.after)
+    }
+}
+
+/// Run every pass until none of them change anything.
+///
+/// Programs start at index 0 with an empty accumulator. If the original halts, the optimized
+/// program halts with the same accumulator, having written the same outputs.
+/// If it loops forever or jumps out of bounds, so does the optimized program.
+/// The exception is overflow - merged `acc`s may overflow at a different point, or not at all.
+pub fn optimize(instructions: &[Instruction]) -> Optimization {
+    let mut optimized = instructions.to_vec();
+    loop {
+        let next = eliminate_dead_code(&thread_jumps(&remove_nops(&merge_accumulates(&optimized))));
+        if next == optimized {
+            break;
+        }
+        optimized = next;
+    }
+    Optimization {
+        before: instructions.len(),
+        after: optimized.len(),
+        instructions: optimized,
+    }
+}
+
+/// Where the jump at `index` lands, or `None` if it faults.
+/// `instructions.len()` means halting.
+fn target(index: usize, offset: isize, len: usize) -> Option<usize> {
+    match (index as isize).checked_add(offset) {
+        Some(t) if t >= 0 && t as usize <= len => Some(t as usize),
+        _ => None,
+    }
+}
+
+/// Indices some `jmp` lands on, plus the start of the program.
+fn jump_targets(instructions: &[Instruction]) -> HashSet<usize> {
+    let mut targets: HashSet<usize> = instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| match *instruction {
+            Jump(j) => target(index, j, instructions.len()),
+            _ => None,
+        })
+        .collect();
+    targets.insert(0);
+    targets
+}
+
+/// Drop the instructions which aren't kept, pointing jumps at the same places as before.
+/// Execution that would have reached a dropped instruction reaches the next kept one instead,
+/// so only drop instructions that do nothing, or which nothing reaches.
+fn compact(instructions: &[Instruction], keep: &[bool]) -> Vec<Instruction> {
+    let len = instructions.len();
+    // Where each old index, and the halt point, end up
+    let mut new_index = Vec::with_capacity(len + 1);
+    let mut kept = 0;
+    for k in keep.iter() {
+        new_index.push(kept as i128);
+        kept += *k as usize;
+    }
+    new_index.push(kept as i128);
+    let new_len = kept as i128;
+
+    instructions
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| keep[*index])
+        .map(|(index, instruction)| match *instruction {
+            Jump(j) => {
+                // Out of bounds stays out of bounds, by the same margin
+                let old = index as i128 + j as i128;
+                let new = match old {
+                    o if o < 0 => o,
+                    o if o > len as i128 => new_len + (o - len as i128),
+                    o => new_index[o as usize],
+                };
+                let offset = new - new_index[index];
+                match offset >= isize::MIN as i128 && offset <= isize::MAX as i128 {
+                    true => Jump(offset as isize),
+                    false => Jump(j),
+                }
+            }
+            ref other => other.clone(),
+        })
+        .collect()
+}
+
+/// Replace runs of `acc` with their sum, unless something jumps into the middle of them.
+pub fn merge_accumulates(instructions: &[Instruction]) -> Vec<Instruction> {
+    let targets = jump_targets(instructions);
+    let mut merged = instructions.to_vec();
+    let mut keep = vec![true; instructions.len()];
+    let mut index = 0;
+    while index < instructions.len() {
+        let mut sum = match instructions[index] {
+            Accumulate(a) => a,
+            _ => {
+                index += 1;
+                continue;
+            }
+        };
+        let start = index;
+        index += 1;
+        while let Some(Accumulate(a)) = instructions.get(index) {
+            match (targets.contains(&index), sum.checked_add(*a)) {
+                (false, Some(s)) => {
+                    sum = s;
+                    keep[index] = false;
+                    index += 1
+                }
+                _ => break,
+            }
+        }
+        merged[start] = Accumulate(sum);
+    }
+    compact(&merged, &keep)
+}
+
+/// Remove instructions that just move on to the next one - `nop`, `acc +0` and `jmp +1`.
+pub fn remove_nops(instructions: &[Instruction]) -> Vec<Instruction> {
+    let keep: Vec<bool> = instructions
+        .iter()
+        .map(|instruction| !matches!(instruction, Noop(_) | Accumulate(0) | Jump(1)))
+        .collect();
+    compact(instructions, &keep)
+}
+
+/// Point jumps which land on another `jmp` at wherever that one goes.
+/// Chains that loop are left alone.
+pub fn thread_jumps(instructions: &[Instruction]) -> Vec<Instruction> {
+    let len = instructions.len();
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| match *instruction {
+            Jump(j) => {
+                let mut seen = HashSet::new();
+                let mut landing = match target(index, j, len) {
+                    Some(t) => t,
+                    None => return Jump(j),
+                };
+                while let Some(Jump(next)) = instructions.get(landing) {
+                    if !seen.insert(landing) {
+                        return Jump(j);
+                    }
+                    landing = match target(landing, *next, len) {
+                        Some(t) => t,
+                        // Keep the fault where it was
+                        None => break,
+                    };
+                }
+                Jump(landing as isize - index as isize)
+            }
+            ref other => other.clone(),
+        })
+        .collect()
+}
+
+/// Remove instructions which execution from index 0 never reaches.
+pub fn eliminate_dead_code(instructions: &[Instruction]) -> Vec<Instruction> {
+    let len = instructions.len();
+    let mut keep = vec![false; len];
+    let mut index = Some(0);
+    while let Some(i) = index {
+        if i >= len || keep[i] {
+            break;
+        }
+        keep[i] = true;
+        index = match instructions[i] {
+            Jump(j) => target(i, j, len),
+            _ => Some(i + 1),
+        };
+    }
+    compact(instructions, &keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExecutionFault, Memory, Status};
+    use proptest::prelude::*;
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\nnop -4\nacc +6\n";
+
+    #[derive(Debug, Eq, PartialEq)]
+    enum Outcome {
+        Halted(isize, Vec<isize>),
+        Loops,
+        Faults,
+    }
+
+    /// Without `in`, a program which revisits an index will loop forever.
+    fn outcome(instructions: &[Instruction]) -> Outcome {
+        let mut memory = Memory::new(instructions.to_vec());
+        let mut visited = HashSet::new();
+        let mut outputs = Vec::new();
+        loop {
+            if !visited.insert(memory.index) {
+                return Outcome::Loops;
+            }
+            memory = match memory.step() {
+                Status::Running(m) => m,
+                Status::Emitted(value, m) => {
+                    outputs.push(value);
+                    m
+                }
+                Status::Halted(m) => return Outcome::Halted(m.accumulator, outputs),
+                Status::Faulted(ExecutionFault::Overflow(_)) => panic!("Operands are too small"),
+                _ => return Outcome::Faults,
+            }
+        }
+    }
+
+    fn parse(s: &str) -> Vec<Instruction> {
+        s.parse::<Memory>().unwrap().instructions
+    }
+
+    #[test]
+    fn example() {
+        let optimization = optimize(&parse(EXAMPLE));
+        assert_eq!(optimization.instructions, parse("acc +8\n"));
+        assert_eq!(optimization.to_string(), "9 -> 1 instructions");
+        assert_eq!(
+            outcome(&optimization.instructions),
+            outcome(&parse(EXAMPLE))
+        );
+    }
+    #[test]
+    fn passes() {
+        assert_eq!(
+            merge_accumulates(&parse("acc +1\nacc +2\njmp +2\nacc +3\nacc +4\njmp -2\n")),
+            // The second run can't be merged, something jumps to the middle of it
+            parse("acc +3\njmp +2\nacc +3\nacc +4\njmp -2\n")
+        );
+        assert_eq!(
+            remove_nops(&parse(
+                "jmp +3\nnop +0\nacc +0\nacc +1\njmp +1\njmp -5\njmp +4\n"
+            )),
+            // The last jump still lands 3 past the end
+            parse("jmp +1\nacc +1\njmp -2\njmp +4\n")
+        );
+        assert_eq!(
+            thread_jumps(&parse("jmp +2\nacc +1\njmp +2\njmp -1\nacc +2\njmp -2\n")),
+            parse("jmp +4\nacc +1\njmp +2\njmp +1\nacc +2\njmp -1\n")
+        );
+        assert_eq!(
+            thread_jumps(&parse("jmp +1\njmp -1\n")),
+            parse("jmp +1\njmp -1\n")
+        );
+        assert_eq!(
+            eliminate_dead_code(&parse("jmp +2\nacc +1\nout +0\njmp +3\nacc +2\n")),
+            parse("jmp +1\nout +0\njmp +2\n")
+        );
+    }
+
+    fn instruction() -> impl Strategy<Value = Instruction> {
+        prop_oneof![
+            (-5..5isize).prop_map(Noop),
+            (-5..5isize).prop_map(Accumulate),
+            (-5..5isize).prop_map(Jump),
+            (-5..5isize).prop_map(Instruction::Output),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn differential(instructions in prop::collection::vec(instruction(), 0..24)) {
+            let optimization = optimize(&instructions);
+            prop_assert!(optimization.after <= optimization.before);
+            prop_assert_eq!(outcome(&optimization.instructions), outcome(&instructions));
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    observer::{Budget, Control, VmObserver},
+    Instruction, Memory, Status,
+};
+use std::{collections::BTreeMap, error, fmt, fs, path};
+
+/// How often each instruction ran, and how much it changed the accumulator in total.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Profile {
+    pub instructions: Vec<Instruction>,
+    pub hits: Vec<u64>,
+    /// Summed without overflow, so a hot `acc` can't hide behind wrapping
+    pub accumulator: Vec<i128>,
+}
+
+impl Profile {
+    pub fn new(instructions: &[Instruction]) -> Self {
+        Self {
+            instructions: instructions.to_vec(),
+            hits: vec![0; instructions.len()],
+            accumulator: vec![0; instructions.len()],
+        }
+    }
+    /// Count one execution of the instruction at `index`.
+    pub fn record(&mut self, index: isize, accumulator_before: isize, accumulator_after: isize) {
+        if let Some(hits) = self.hits.get_mut(index as usize) {
+            *hits += 1;
+            self.accumulator[index as usize] +=
+                accumulator_after as i128 - accumulator_before as i128;
+        }
+    }
+    /// Run `memory` for at most `max_steps` instructions, counting as we go.
+    /// Outputs are skipped over, so this stops when the program halts, faults, waits for input,
+    /// or runs out of steps - in which case the status is `Running`.
+    pub fn run(mut memory: Memory, max_steps: usize) -> (Status, Self) {
+        let mut observers = (Budget(max_steps), Self::new(&memory.instructions));
+        loop {
+            match memory.run_observed(&mut observers) {
+                Status::Emitted(_, m) => memory = m,
+                status => return (status, observers.1),
+            }
+        }
+    }
+    pub fn steps(&self) -> u64 {
+        self.hits.iter().sum()
+    }
+    /// Indices of instructions which never ran.
+    pub fn unexecuted(&self) -> Vec<usize> {
+        (0..self.hits.len())
+            .filter(|i| self.hits[*i] == 0)
+            .collect()
+    }
+    /// Total executions of each mnemonic, including those which never ran.
+    pub fn mnemonic_hits(&self) -> BTreeMap<&'static str, u64> {
+        let mut counts = BTreeMap::new();
+        for (instruction, hits) in self.instructions.iter().zip(self.hits.iter()) {
+            *counts.entry(instruction.mnemonic()).or_insert(0) += hits;
+        }
+        counts
+    }
+    /// The `n` most executed indices, most first. Ties go to the lower index.
+    pub fn hottest(&self, n: usize) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.hits.len()).collect();
+        indices.sort_by(|a, b| self.hits[*b].cmp(&self.hits[*a]).then(a.cmp(b)));
+        indices.truncate(n);
+        indices
+    }
+    pub fn to_json(&self) -> String {
+        let mut json = format!("{{\"steps\":{},\"instructions\":[", self.steps());
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            json.push_str(&format!(
+                "{{\"index\":{},\"instruction\":\"{}\",\"hits\":{},\"accumulator\":{}}}",
+                index, instruction, self.hits[index], self.accumulator[index]
+            ));
+        }
+        json.push_str("]}");
+        json
+    }
+    pub fn to_file<P: AsRef<path::Path>>(&self, path: P) -> Result<(), Box<dyn error::Error>> {
+        Ok(fs::write(path, self.to_json())?)
+    }
+}
+
+impl VmObserver for Profile {
+    fn after_step(&mut self, index: isize, accumulator: isize, memory: &Memory) -> Control {
+        self.record(index, accumulator, memory.accumulator);
+        Control::Continue
+    }
+}
+
+/// An annotated listing: hits and accumulator change, then the instruction.
+/// Instructions which never ran are marked with `-`.
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>8} {:>8} {:>5}", "hits", "acc", "index")?;
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            match self.hits[index] {
+                0 => write!(f, "{:>8} {:>8}", "-", "")?,
+                hits => write!(f, "{:>8} {:>+8}", hits, self.accumulator[index])?,
+            }
+            writeln!(f, " {:>5} {}", index, instruction)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExecutionFault;
+
+    // Goes round once per input, since there are no conditional jumps
+    const LOOP: &str = "in +0\nacc +2\nout +0\njmp -3\nacc +6\n";
+
+    fn profile() -> Profile {
+        let mut memory: Memory = LOOP.parse().unwrap();
+        memory.inputs = vec![1, 2, 3].into();
+        let (status, profile) = Profile::run(memory, 100);
+        assert!(matches!(status, Status::AwaitingInput(_)));
+        profile
+    }
+
+    #[test]
+    fn counts() {
+        let profile = profile();
+        assert_eq!(profile.hits, vec![3, 3, 3, 3, 0]);
+        // Each `in` replaces 0, 3, 4 with 1, 2, 3
+        assert_eq!(profile.accumulator, vec![-1, 6, 0, 0, 0]);
+        assert_eq!(profile.steps(), 12);
+        assert_eq!(profile.unexecuted(), vec![4]);
+        assert_eq!(profile.hottest(2), vec![0, 1]);
+        assert_eq!(
+            profile.mnemonic_hits().into_iter().collect::<Vec<_>>(),
+            vec![("acc", 3), ("in", 3), ("jmp", 3), ("out", 3)]
+        );
+    }
+    #[test]
+    fn limits_and_faults() {
+        let (status, profile) = Profile::run("jmp +0\n".parse().unwrap(), 10);
+        assert!(matches!(status, Status::Running(_)));
+        assert_eq!(profile.hits, vec![10]);
+        let (status, profile) = Profile::run("acc +1\njmp -2\n".parse().unwrap(), 10);
+        assert!(matches!(
+            status,
+            Status::Faulted(ExecutionFault::NegativeIndex(_))
+        ));
+        assert_eq!(profile.hits, vec![1, 0]);
+    }
+    #[test]
+    fn listing() {
+        assert_eq!(
+            profile().to_string(),
+            "    hits      acc index
+       3       -1     0 in +0
+       3       +6     1 acc +2
+       3       +0     2 out +0
+       3       +0     3 jmp -3
+       -              4 acc +6
+"
+        );
+    }
+    #[test]
+    fn json() {
+        let (_, profile) = Profile::run("acc -1\n".parse().unwrap(), 10);
+        assert_eq!(
+            profile.to_json(),
+            r#"{"steps":1,"instructions":[{"index":0,"instruction":"acc -1","hits":1,"accumulator":-1}]}"#
+        );
+    }
+}
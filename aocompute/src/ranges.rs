@@ -0,0 +1,278 @@
+use crate::{Instruction, Memory};
+use std::{collections::BTreeSet, fmt};
+
+/// How many times the range at an instruction may grow before its growing ends are widened
+/// to the limits of `isize`, so that loops are analysed in bounded time.
+const WIDEN_AFTER: u32 = 3;
+
+/// The accumulator values from `low` to `high` inclusive.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Interval {
+    pub low: isize,
+    pub high: isize,
+}
+
+impl Interval {
+    pub const ANY: Self = Self {
+        low: isize::MIN,
+        high: isize::MAX,
+    };
+    pub fn point(value: isize) -> Self {
+        Self {
+            low: value,
+            high: value,
+        }
+    }
+    pub fn contains(&self, value: isize) -> bool {
+        self.low <= value && value <= self.high
+    }
+    pub fn is_non_negative(&self) -> bool {
+        self.low >= 0
+    }
+    fn join(&self, other: &Self) -> Self {
+        Self {
+            low: self.low.min(other.low),
+            high: self.high.max(other.high),
+        }
+    }
+    /// `other` joined in, with any end that moved pushed all the way out.
+    fn widen(&self, other: &Self) -> Self {
+        Self {
+            low: match other.low < self.low {
+                true => isize::MIN,
+                false => self.low,
+            },
+            high: match other.high > self.high {
+                true => isize::MAX,
+                false => self.high,
+            },
+        }
+    }
+    /// The values which `acc operand` takes without overflowing, and whether any would overflow.
+    fn add(&self, operand: isize) -> (Option<Self>, bool) {
+        match (
+            self.low.checked_add(operand),
+            self.high.checked_add(operand),
+        ) {
+            (Some(low), Some(high)) => (Some(Self { low, high }), false),
+            (Some(low), None) => (
+                Some(Self {
+                    low,
+                    high: isize::MAX,
+                }),
+                true,
+            ),
+            (None, Some(high)) => (
+                Some(Self {
+                    low: isize::MIN,
+                    high,
+                }),
+                true,
+            ),
+            (None, None) => (None, true),
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.low, self.high)
+    }
+}
+
+/// The accumulator values possible at each instruction, over every path from the start,
+/// found without running the program.
+/// The ranges are sound but not always tight: every value a run can see is included,
+/// but loops which change the accumulator are widened to the limits of `isize`, and `in`
+/// could read anything.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Ranges {
+    /// One for each instruction, then one for the halt point. `None` if it's unreachable.
+    pub at: Vec<Option<Interval>>,
+    /// `acc` instructions which might overflow
+    pub may_overflow: BTreeSet<usize>,
+}
+
+impl Ranges {
+    /// Analyse from `memory.index`, with `memory.accumulator`.
+    pub fn new(memory: &Memory) -> Self {
+        Self::analyse(memory, None)
+    }
+    /// As `new`, but as if the instruction at `index` were `instruction`, without copying
+    /// the program. For weighing up repairs.
+    pub fn patched(memory: &Memory, index: usize, instruction: &Instruction) -> Self {
+        Self::analyse(memory, Some((index, instruction)))
+    }
+    fn analyse(memory: &Memory, patch: Option<(usize, &Instruction)>) -> Self {
+        let len = memory.instructions.len();
+        let mut ranges = Self {
+            at: vec![None; len + 1],
+            may_overflow: BTreeSet::new(),
+        };
+        let mut growths = vec![0; len + 1];
+        let mut worklist = Vec::new();
+        if memory.index >= 0 && memory.index as usize <= len {
+            ranges.at[memory.index as usize] = Some(Interval::point(memory.accumulator));
+            worklist.push(memory.index as usize);
+        }
+        while let Some(index) = worklist.pop() {
+            let interval = match (index < len, ranges.at[index]) {
+                (true, Some(interval)) => interval,
+                _ => continue,
+            };
+            let instruction = match patch {
+                Some((i, instruction)) if i == index => instruction,
+                _ => &memory.instructions[index],
+            };
+            let (next, offset) = match *instruction {
+                Instruction::Accumulate(a) => {
+                    let (next, overflows) = interval.add(a);
+                    if overflows {
+                        ranges.may_overflow.insert(index);
+                    }
+                    (next, 1)
+                }
+                Instruction::Jump(j) => (Some(interval), j),
+                Instruction::Input(_) => (Some(Interval::ANY), 1),
+                Instruction::Noop(_) | Instruction::Output(_) => (Some(interval), 1),
+            };
+            // Anything else faults, so goes nowhere
+            let (next, target) = match (next, (index as isize).checked_add(offset)) {
+                (Some(next), Some(t)) if t >= 0 && t as usize <= len => (next, t as usize),
+                _ => continue,
+            };
+            let joined = match ranges.at[target] {
+                None => next,
+                Some(old) => {
+                    let joined = old.join(&next);
+                    if joined == old {
+                        continue;
+                    }
+                    growths[target] += 1;
+                    match growths[target] > WIDEN_AFTER {
+                        true => old.widen(&joined),
+                        false => joined,
+                    }
+                }
+            };
+            ranges.at[target] = Some(joined);
+            worklist.push(target);
+        }
+        ranges
+    }
+    /// The possible accumulators on reaching `index`, or `None` if it can't be reached.
+    pub fn at(&self, index: usize) -> Option<Interval> {
+        self.at.get(index).copied().flatten()
+    }
+    /// The possible accumulators on halting.
+    /// `None` proves the program never halts - `Some` doesn't prove that it does.
+    pub fn at_halt(&self) -> Option<Interval> {
+        self.at.last().copied().flatten()
+    }
+    /// Whether the accumulator might overflow. `false` proves it never does.
+    pub fn may_overflow(&self) -> bool {
+        !self.may_overflow.is_empty()
+    }
+    /// Instructions which are never reached.
+    pub fn unreachable(&self) -> Vec<usize> {
+        (0..self.at.len() - 1)
+            .filter(|index| self.at[*index].is_none())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExecutionFault, Status};
+    use proptest::prelude::*;
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n";
+
+    #[test]
+    fn straight_line() {
+        let ranges = Ranges::new(&"acc +3\nacc -1\nout +0\n".parse().unwrap());
+        assert_eq!(ranges.at(1), Some(Interval::point(3)));
+        assert_eq!(ranges.at_halt(), Some(Interval::point(2)));
+        assert!(ranges.at_halt().unwrap().is_non_negative());
+        assert!(!ranges.may_overflow());
+    }
+    #[test]
+    fn example() {
+        let memory: Memory = EXAMPLE.parse().unwrap();
+        let ranges = Ranges::new(&memory);
+        assert_eq!(ranges.at_halt(), None);
+        assert_eq!(ranges.unreachable(), vec![5, 8]);
+        // Looping forever, the accumulator would eventually overflow
+        assert_eq!(
+            ranges.may_overflow.iter().collect::<Vec<_>>(),
+            vec![&1, &3, &6]
+        );
+        assert_eq!(ranges.at(1).unwrap().high, isize::MAX);
+
+        let repaired = Ranges::patched(&memory, 7, &Instruction::Noop(-4));
+        assert_eq!(repaired.at_halt(), Some(Interval::point(8)));
+        assert!(!repaired.may_overflow());
+        assert_eq!(Ranges::new(&memory), ranges);
+    }
+    #[test]
+    fn inputs() {
+        let ranges = Ranges::new(&"in +0\nacc +2\njmp +2\nacc -5\n".parse().unwrap());
+        assert_eq!(ranges.at(1), Some(Interval::ANY));
+        assert_eq!(ranges.may_overflow.iter().collect::<Vec<_>>(), vec![&1]);
+        assert_eq!(
+            ranges.at_halt(),
+            Some(Interval {
+                low: isize::MIN + 2,
+                high: isize::MAX
+            })
+        );
+        assert_eq!(ranges.unreachable(), vec![3]);
+    }
+    #[test]
+    fn overflow() {
+        let mut memory: Memory = "acc +1\nacc -1\n".parse().unwrap();
+        memory.accumulator = isize::MAX;
+        let ranges = Ranges::new(&memory);
+        assert_eq!(ranges.may_overflow.iter().collect::<Vec<_>>(), vec![&0]);
+        assert_eq!(ranges.at(1), None);
+        assert_eq!(ranges.at_halt(), None);
+    }
+
+    fn instruction() -> impl Strategy<Value = Instruction> {
+        prop_oneof![
+            (-4..4isize).prop_map(Instruction::Noop),
+            prop_oneof![(-4..4isize), Just(isize::MAX), Just(isize::MIN)]
+                .prop_map(Instruction::Accumulate),
+            (-4..4isize).prop_map(Instruction::Jump),
+            (-4..4isize).prop_map(Instruction::Input),
+            (-4..4isize).prop_map(Instruction::Output),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn sound(
+            instructions in prop::collection::vec(instruction(), 0..16),
+            inputs in prop::collection::vec(-9..9isize, 0..4),
+        ) {
+            let mut memory = Memory::new(instructions);
+            memory.inputs = inputs.into();
+            let ranges = Ranges::new(&memory);
+            for _ in 0..200 {
+                let index = memory.index as usize;
+                let interval = ranges.at(index);
+                prop_assert!(interval.is_some_and(|i| i.contains(memory.accumulator)));
+                match memory.step() {
+                    Status::Running(m) | Status::Emitted(_, m) => memory = m,
+                    Status::Faulted(ExecutionFault::Overflow(_)) => {
+                        prop_assert!(ranges.may_overflow.contains(&index));
+                        break;
+                    }
+                    _ => break,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::isa::{Cpu, Fault, Opcode, Registry};
+use crate::ParseReason;
+use std::{fmt, sync::Arc};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Kind {
+    Register,
+    Immediate,
+}
+
+type Semantics = fn(&[isize], &mut Cpu) -> Result<(), Fault>;
+
+/// An opcode whose operands are named registers or signed integers.
+/// Registers are decoded to their number in `Cpu`, so `acc` is 0 and the rest follow in order.
+struct RegisterOp {
+    mnemonic: &'static str,
+    kinds: &'static [Kind],
+    names: Arc<Vec<String>>,
+    semantics: Semantics,
+}
+
+impl fmt::Debug for RegisterOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RegisterOp({:?}, {:?})", self.mnemonic, self.kinds)
+    }
+}
+
+impl Opcode for RegisterOp {
+    fn mnemonic(&self) -> &str {
+        self.mnemonic
+    }
+    fn arity(&self) -> usize {
+        self.kinds.len()
+    }
+    fn parse_operand(&self, position: usize, word: &str) -> Result<isize, ParseReason> {
+        match self.kinds[position] {
+            Kind::Register => match self.names.iter().position(|name| name == word) {
+                Some(register) => Ok(register as isize),
+                None => Err(ParseReason::UnknownRegister(word.to_string())),
+            },
+            Kind::Immediate => word.parse().map_err(ParseReason::BadInteger),
+        }
+    }
+    fn render_operand(&self, position: usize, operand: isize) -> String {
+        match self.kinds[position] {
+            Kind::Register => self.names[operand as usize].clone(),
+            Kind::Immediate => format!("{:+}", operand),
+        }
+    }
+    fn execute(&self, operands: &[isize], cpu: &mut Cpu) -> Result<(), Fault> {
+        (self.semantics)(operands, cpu)
+    }
+}
+
+fn register(operand: isize) -> usize {
+    operand as usize
+}
+
+impl Registry {
+    /// The default instruction set, plus these, where `r` is a register and `n` an integer:
+    /// - `set r n`: put `n` in `r`
+    /// - `mov r1 r2`: copy `r2` into `r1`
+    /// - `add r1 r2`: add `r2` to `r1`
+    /// - `jnz r n`: jump by `n` if `r` isn't zero
+    /// - `load r1 r2`: put the data at address `r2` in `r1`
+    /// - `store r1 r2`: put `r1` in the data at address `r2`
+    ///
+    /// `acc` is always a register name, so existing programs keep working.
+    pub fn with_registers(names: &[&str]) -> Self {
+        use Kind::*;
+        let names: Arc<Vec<String>> = Arc::new(
+            std::iter::once("acc")
+                .chain(names.iter().copied())
+                .map(String::from)
+                .collect(),
+        );
+        let ops: [(&'static str, &'static [Kind], Semantics); 6] = [
+            ("set", &[Register, Immediate], |o, cpu| {
+                cpu.set_register(register(o[0]), o[1]);
+                cpu.jump(1)
+            }),
+            ("mov", &[Register, Register], |o, cpu| {
+                cpu.set_register(register(o[0]), cpu.register(register(o[1])));
+                cpu.jump(1)
+            }),
+            ("add", &[Register, Register], |o, cpu| {
+                let (a, b) = (cpu.register(register(o[0])), cpu.register(register(o[1])));
+                cpu.set_register(register(o[0]), a.checked_add(b).ok_or(Fault::Overflow)?);
+                cpu.jump(1)
+            }),
+            ("jnz", &[Register, Immediate], |o, cpu| {
+                match cpu.register(register(o[0])) {
+                    0 => cpu.jump(1),
+                    _ => cpu.jump(o[1]),
+                }
+            }),
+            ("load", &[Register, Register], |o, cpu| {
+                let value = cpu.load(cpu.register(register(o[1])));
+                cpu.set_register(register(o[0]), value);
+                cpu.jump(1)
+            }),
+            ("store", &[Register, Register], |o, cpu| {
+                let address = cpu.register(register(o[1]));
+                cpu.store(address, cpu.register(register(o[0])));
+                cpu.jump(1)
+            }),
+        ];
+        let mut registry = Self::default();
+        for &(mnemonic, kinds, semantics) in ops.iter() {
+            registry.register(RegisterOp {
+                mnemonic,
+                kinds,
+                names: names.clone(),
+                semantics,
+            });
+        }
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Memory, ParseInstructionError};
+
+    #[test]
+    fn existing_programs_still_run() {
+        let source = "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\nnop -4\nacc +6\n";
+        let memory: Memory = source.parse().unwrap();
+        let mut machine = Registry::with_registers(&["a", "b"]).parse(source).unwrap();
+        assert_eq!(machine, (&memory).into());
+        assert_eq!(machine.run(100), Ok(true));
+        assert_eq!(machine.cpu.accumulator, 8);
+    }
+    #[test]
+    fn multiply_with_a_loop() {
+        let source = "set a +5\nset b +4\nset c -1\nadd acc a\nadd b c\njnz b -2\nmov d acc\n";
+        let registry = Registry::with_registers(&["a", "b", "c", "d"]);
+        let mut machine = registry.parse(source).unwrap();
+        assert_eq!(machine.to_string(), source);
+        assert_eq!(machine.run(100), Ok(true));
+        assert_eq!(machine.cpu.accumulator, 20);
+        assert_eq!(machine.cpu.registers, vec![5, 0, -1, 20]);
+    }
+    #[test]
+    fn load_and_store() {
+        let source = "set a +7\nset b +100\nstore a b\nload acc b\nset b -3\nload a b\n";
+        let mut machine = Registry::with_registers(&["a", "b"]).parse(source).unwrap();
+        assert_eq!(machine.run(100), Ok(true));
+        assert_eq!(machine.cpu.accumulator, 7);
+        assert_eq!(machine.cpu.register(1), 0);
+        assert_eq!(
+            machine.cpu.data.into_iter().collect::<Vec<_>>(),
+            vec![(100, 7)]
+        );
+    }
+    #[test]
+    fn unknown_register() {
+        let e = Registry::with_registers(&["a"])
+            .parse("mov a b\n")
+            .unwrap_err();
+        assert_eq!(
+            e.errors,
+            vec![ParseInstructionError {
+                line: 1,
+                columns: 6..7,
+                reason: ParseReason::UnknownRegister(String::from("b"))
+            }]
+        );
+    }
+}
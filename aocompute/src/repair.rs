@@ -0,0 +1,293 @@
+use crate::{Instruction, Memory};
+use std::collections::HashMap;
+
+/// Swapping the instruction at `index` for `instruction` makes the program halt,
+/// with `accumulator` in the accumulator.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Repair {
+    pub index: usize,
+    pub instruction: Instruction,
+    pub accumulator: isize,
+}
+
+/// The `nop`<->`jmp` swap of an instruction, if it has one.
+pub fn flip(instruction: &Instruction) -> Option<Instruction> {
+    match *instruction {
+        Instruction::Noop(n) => Some(Instruction::Jump(n)),
+        Instruction::Jump(j) => Some(Instruction::Noop(j)),
+        _ => None,
+    }
+}
+
+/// Where execution goes after `instruction` at `index`.
+/// `None` if that would fault - the halt point is `len`.
+/// `in` is treated as a dead end too, since we can't know the accumulator after it.
+fn successor(index: usize, instruction: &Instruction, len: usize) -> Option<usize> {
+    let target = match *instruction {
+        Instruction::Jump(j) => (index as isize).checked_add(j)?,
+        Instruction::Input(_) => return None,
+        _ => index as isize + 1,
+    };
+    match target {
+        t if t < 0 || t as usize > len => None,
+        t => Some(t as usize),
+    }
+}
+
+/// The control flow graph of a program.
+/// Every instruction has at most one successor, so the instructions that can reach the
+/// halt point form a tree rooted there, which we walk once to answer everything.
+#[derive(Debug)]
+pub struct ControlFlow {
+    pub successors: Vec<Option<usize>>,
+    /// Accumulator change for each instruction, followed by 0 for the halt point
+    deltas: Vec<i128>,
+    reaches_halt: Vec<bool>,
+    /// Euler tour of the halt tree, so that "is `a` on the way to halt from `b`" is O(1)
+    enter: Vec<usize>,
+    exit: Vec<usize>,
+    /// Relative to the accumulator on arrival: total change, and the lowest and highest
+    /// partial sums on the way to halt
+    total: Vec<i128>,
+    lowest: Vec<i128>,
+    highest: Vec<i128>,
+}
+
+impl ControlFlow {
+    pub fn new(memory: &Memory) -> Self {
+        let len = memory.instructions.len();
+        let successors: Vec<Option<usize>> = memory
+            .instructions
+            .iter()
+            .enumerate()
+            .map(|(index, instruction)| successor(index, instruction, len))
+            .collect();
+        let mut deltas: Vec<i128> = memory
+            .instructions
+            .iter()
+            .map(|instruction| match *instruction {
+                Instruction::Accumulate(a) => a as i128,
+                _ => 0,
+            })
+            .collect();
+        deltas.push(0);
+
+        let mut predecessors = vec![Vec::new(); len + 1];
+        for (index, s) in successors.iter().enumerate() {
+            if let Some(s) = s {
+                predecessors[*s].push(index);
+            }
+        }
+
+        let mut control_flow = Self {
+            successors,
+            deltas,
+            reaches_halt: vec![false; len + 1],
+            enter: vec![0; len + 1],
+            exit: vec![0; len + 1],
+            total: vec![0; len + 1],
+            lowest: vec![0; len + 1],
+            highest: vec![0; len + 1],
+        };
+
+        // Iterative DFS from the halt point, so long programs don't blow the stack
+        let mut clock = 0;
+        let mut stack = vec![(len, 0)];
+        control_flow.reaches_halt[len] = true;
+        while let Some((node, child)) = stack.pop() {
+            if child == 0 {
+                control_flow.enter[node] = clock;
+                clock += 1;
+                if node != len {
+                    let s = control_flow.successors[node].expect("Tree nodes have successors");
+                    let total = control_flow.deltas[node] + control_flow.total[s];
+                    control_flow.total[node] = total;
+                    control_flow.lowest[node] =
+                        0.min(control_flow.deltas[node] + control_flow.lowest[s]);
+                    control_flow.highest[node] =
+                        0.max(control_flow.deltas[node] + control_flow.highest[s]);
+                }
+            }
+            match predecessors[node].get(child) {
+                Some(&p) => {
+                    stack.push((node, child + 1));
+                    control_flow.reaches_halt[p] = true;
+                    stack.push((p, 0));
+                }
+                None => control_flow.exit[node] = clock,
+            }
+        }
+        control_flow
+    }
+    /// Whether execution starting at `index` will eventually reach the halt point.
+    /// Ignores accumulator overflow.
+    pub fn reaches_halt(&self, index: usize) -> bool {
+        self.reaches_halt[index]
+    }
+    /// Whether `via` lies on the path from `from` to the halt point (inclusive).
+    fn passes_through(&self, from: usize, via: usize) -> bool {
+        self.reaches_halt[from]
+            && self.reaches_halt[via]
+            && self.enter[via] <= self.enter[from]
+            && self.enter[from] < self.exit[via]
+    }
+    /// The accumulator at halt, arriving at `index` with `accumulator`.
+    /// `None` if we'd never halt, or the accumulator would overflow on the way.
+    fn halts_with(&self, index: usize, accumulator: isize) -> Option<isize> {
+        let accumulator = accumulator as i128;
+        let fits = |a: i128| a >= isize::MIN as i128 && a <= isize::MAX as i128;
+        match self.reaches_halt[index]
+            && fits(accumulator + self.lowest[index])
+            && fits(accumulator + self.highest[index])
+        {
+            true => Some((accumulator + self.total[index]) as isize),
+            false => None,
+        }
+    }
+}
+
+/// Every single `nop`<->`jmp` swap that makes `memory` halt, in index order.
+/// Runs in time linear in the length of the program.
+pub fn repairs(memory: &Memory) -> Vec<Repair> {
+    let len = memory.instructions.len();
+    let control_flow = ControlFlow::new(memory);
+
+    // Run the program as-is, noting the accumulator when each instruction is first reached.
+    // A swap can only change anything if execution actually gets to it.
+    let mut arrivals = HashMap::new();
+    let mut halted_with = None;
+    let mut state = memory.clone();
+    loop {
+        if state.index >= 0 && state.index as usize == len {
+            halted_with = Some(state.accumulator);
+            break;
+        }
+        if state.index < 0 || arrivals.contains_key(&(state.index as usize)) {
+            break;
+        }
+        arrivals.insert(state.index as usize, state.accumulator);
+        state = match state.try_execute_instruction() {
+            Ok(state) => state,
+            Err(_) => break,
+        };
+    }
+
+    memory
+        .instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| {
+            let replacement = flip(instruction)?;
+            let accumulator = match arrivals.get(&index) {
+                Some(&accumulator) => {
+                    // Coming back round to the swapped instruction would loop forever
+                    let target = successor(index, &replacement, len)?;
+                    if control_flow.passes_through(target, index) {
+                        return None;
+                    }
+                    control_flow.halts_with(target, accumulator)?
+                }
+                None => halted_with?,
+            };
+            Some(Repair {
+                index,
+                instruction: replacement,
+                accumulator,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::immutable::{ImmutableMemory, ImmutableStatus};
+    use Instruction::*;
+
+    fn brute_force(memory: &Memory) -> Vec<Repair> {
+        let mut found = Vec::new();
+        for (index, instruction) in memory.instructions.iter().enumerate() {
+            if let Some(replacement) = flip(instruction) {
+                let mut instructions = memory.instructions.clone();
+                instructions[index] = replacement.clone();
+                if let ImmutableStatus::Halted(m) = ImmutableMemory::new(instructions).run() {
+                    found.push(Repair {
+                        index,
+                        instruction: replacement,
+                        accumulator: m.memory.accumulator,
+                    })
+                }
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn example() {
+        let memory: Memory =
+            "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            repairs(&memory),
+            vec![Repair {
+                index: 7,
+                instruction: Noop(-4),
+                accumulator: 8
+            }]
+        );
+    }
+    #[test]
+    fn agrees_with_brute_force() {
+        let programs = [
+            "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n",
+            // Already halts, so every swap off the path still halts
+            "acc +1\njmp +2\nnop +5\nacc +2\n",
+            // Swapping the only jump back onto itself
+            "nop +0\njmp +0\n",
+            "jmp +2\nacc +1\nnop -2\njmp -1\nacc +4\n",
+            "nop +3\njmp +1\nacc +7\njmp -2\nnop -4\n",
+        ];
+        for program in programs.iter() {
+            let memory: Memory = program.parse().unwrap();
+            assert_eq!(repairs(&memory), brute_force(&memory), "{}", program);
+        }
+    }
+    #[test]
+    fn overflow_is_not_a_repair() {
+        let memory = Memory::new(vec![
+            Accumulate(isize::MAX),
+            Noop(2),
+            Jump(0),
+            Accumulate(1),
+        ]);
+        assert_eq!(repairs(&memory), vec![]);
+        assert_eq!(brute_force(&memory), vec![]);
+    }
+    #[test]
+    fn long_program() {
+        // A long chain of jumps that loops back to the start, with a nop in the middle
+        // which can be swapped to jump to the halt point
+        let n = 50_000;
+        let mut instructions: Vec<Instruction> = (0..n).map(|_| Jump(1)).collect();
+        instructions[n - 1] = Jump(-(n as isize - 1));
+        instructions[n / 2] = Noop(n as isize / 2);
+        instructions[1] = Accumulate(3);
+        let found = repairs(&Memory::new(instructions));
+        assert_eq!(
+            found,
+            vec![
+                Repair {
+                    index: n / 2,
+                    instruction: Jump(n as isize / 2),
+                    accumulator: 3
+                },
+                Repair {
+                    index: n - 1,
+                    instruction: Noop(-(n as isize - 1)),
+                    accumulator: 3
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Saving and restoring machine state as JSON, with the `serde` feature.
+//! `Memory` and `ImmutableMemory` (including the visited indices) can be snapshotted,
+//! so a long search can be checkpointed, or a failing state attached to a bug report.
+//! For a smaller binary snapshot, see `Memory::to_bytes` and `ImmutableMemory::to_bytes`.
+use serde::{de::DeserializeOwned, Serialize, Serializer};
+use std::{collections::HashSet, error, fs, path};
+
+pub fn to_json<T: Serialize>(state: &T) -> Result<String, serde_json::Error> {
+    serde_json::to_string_pretty(state)
+}
+
+pub fn from_json<T: DeserializeOwned>(json: &str) -> Result<T, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+pub fn save<T: Serialize, P: AsRef<path::Path>>(
+    state: &T,
+    path: P,
+) -> Result<(), Box<dyn error::Error>> {
+    Ok(fs::write(path, to_json(state)?)?)
+}
+
+pub fn load<T: DeserializeOwned, P: AsRef<path::Path>>(
+    path: P,
+) -> Result<T, Box<dyn error::Error>> {
+    Ok(from_json(&fs::read_to_string(path)?)?)
+}
+
+/// So that the same state always gives the same snapshot.
+pub(crate) fn sorted<S: Serializer>(
+    set: &HashSet<isize>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut sorted: Vec<&isize> = set.iter().collect();
+    sorted.sort_unstable();
+    sorted.serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        immutable::{ImmutableMemory, ImmutableStatus},
+        Memory,
+    };
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n";
+
+    #[test]
+    fn memory() {
+        let mut memory: Memory = "acc -3\njmp +2\n".parse().unwrap();
+        memory.inputs.push_back(4);
+        let json = to_json(&memory).unwrap();
+        assert_eq!(
+            json.split_whitespace().collect::<String>(),
+            r#"{"instructions":[{"Accumulate":-3},{"Jump":2}],"accumulator":0,"index":0,"inputs":[4]}"#
+        );
+        assert_eq!(from_json::<Memory>(&json).unwrap(), memory);
+    }
+    #[test]
+    fn checkpoint_and_resume() {
+        let mut immutable_memory: ImmutableMemory = EXAMPLE.parse().unwrap();
+        for _ in 0..4 {
+            immutable_memory = immutable_memory.execute_instruction();
+        }
+        let path =
+            std::env::temp_dir().join(format!("aocompute-snapshot-{}.json", std::process::id()));
+        save(&immutable_memory, &path).unwrap();
+        let restored: ImmutableMemory = load(&path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(restored, immutable_memory);
+        assert_eq!(
+            to_json(&restored).unwrap(),
+            to_json(&immutable_memory).unwrap()
+        );
+        match restored.run() {
+            ImmutableStatus::WouldRevisit(m) => assert_eq!(m.memory.accumulator, 5),
+            _ => panic!(),
+        }
+        assert!(from_json::<ImmutableMemory>("{\"memory\": 1}").is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{Instruction, Memory, Status};
+
+/// The registers after some number of steps, without the program.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct State {
+    /// How many instructions have executed, so the starting state is step 0
+    pub step: usize,
+    pub index: isize,
+    pub accumulator: isize,
+    /// What the instruction which led here wrote, if it was an `out`
+    pub output: Option<isize>,
+}
+
+impl State {
+    fn of(memory: &Memory, step: usize, output: Option<isize>) -> Self {
+        Self {
+            step,
+            index: memory.index,
+            accumulator: memory.accumulator,
+            output,
+        }
+    }
+}
+
+/// An item from `Memory::steps`.
+#[derive(Debug)]
+pub enum Progress {
+    At(State),
+    /// Why the run ended, always the last item. Never `Running` or `Emitted`.
+    Stopped(Status),
+}
+
+impl Progress {
+    pub fn state(self) -> Option<State> {
+        match self {
+            Progress::At(state) => Some(state),
+            Progress::Stopped(_) => None,
+        }
+    }
+    pub fn status(self) -> Option<Status> {
+        match self {
+            Progress::At(_) => None,
+            Progress::Stopped(status) => Some(status),
+        }
+    }
+}
+
+/// Runs a `Memory` one instruction per item, carrying on past outputs.
+#[derive(Debug, Clone)]
+pub struct Steps {
+    memory: Option<Memory>,
+    step: usize,
+    started: bool,
+}
+
+impl Iterator for Steps {
+    type Item = Progress;
+    fn next(&mut self) -> Option<Self::Item> {
+        let memory = self.memory.take()?;
+        if !self.started {
+            self.started = true;
+            let state = State::of(&memory, 0, None);
+            self.memory = Some(memory);
+            return Some(Progress::At(state));
+        }
+        let memory = match Status::from(memory) {
+            Status::Running(memory) => memory,
+            status => return Some(Progress::Stopped(status)),
+        };
+        let output = match memory.instructions[memory.index as usize] {
+            Instruction::Output(_) => Some(memory.accumulator),
+            _ => None,
+        };
+        match memory.try_execute_instruction() {
+            Ok(memory) => {
+                self.step += 1;
+                let state = State::of(&memory, self.step, output);
+                self.memory = Some(memory);
+                Some(Progress::At(state))
+            }
+            Err(fault) => Some(Progress::Stopped(Status::Faulted(fault))),
+        }
+    }
+}
+
+impl Memory {
+    /// The starting state, then the state after each instruction, then how the run stopped.
+    /// Never ends if the program loops forever, so bound it with `take` or similar.
+    pub fn steps(self) -> Steps {
+        Steps {
+            memory: Some(self),
+            step: 0,
+            started: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n";
+
+    #[test]
+    fn accumulator_at_step() {
+        let memory: Memory = "acc +1\njmp -1\n".parse().unwrap();
+        let state = memory.steps().nth(1000).and_then(Progress::state).unwrap();
+        assert_eq!((state.step, state.index, state.accumulator), (1000, 0, 500));
+    }
+    #[test]
+    fn first_revisit() {
+        let memory: Memory = EXAMPLE.parse().unwrap();
+        let mut seen = std::collections::HashSet::new();
+        let revisit = memory
+            .steps()
+            .filter_map(Progress::state)
+            .find(|state| !seen.insert(state.index))
+            .unwrap();
+        assert_eq!(
+            (revisit.step, revisit.index, revisit.accumulator),
+            (7, 1, 5)
+        );
+    }
+    #[test]
+    fn ends_with_status() {
+        let memory: Memory = "acc +2\nout +0\nacc -1\n".parse().unwrap();
+        let progress: Vec<Progress> = memory.steps().collect();
+        assert_eq!(progress.len(), 5);
+        let outputs: Vec<isize> = progress
+            .iter()
+            .filter_map(|p| match p {
+                Progress::At(state) => state.output,
+                Progress::Stopped(_) => None,
+            })
+            .collect();
+        assert_eq!(outputs, vec![2]);
+        match progress.into_iter().last().and_then(Progress::status) {
+            Some(Status::Halted(m)) => assert_eq!(m.accumulator, 1),
+            _ => panic!(),
+        }
+
+        let memory: Memory = "acc +1\njmp -2\n".parse().unwrap();
+        let mut steps = memory.steps();
+        assert_eq!(
+            steps
+                .by_ref()
+                .take_while(|p| matches!(p, Progress::At(_)))
+                .count(),
+            2
+        );
+        assert!(steps.next().is_none());
+        let memory: Memory = "acc +1\nin +0\n".parse().unwrap();
+        match memory.steps().last().and_then(Progress::status) {
+            Some(Status::AwaitingInput(m)) => assert_eq!(m.index, 1),
+            _ => panic!(),
+        }
+    }
+}
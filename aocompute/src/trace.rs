@@ -0,0 +1,336 @@
+use crate::{
+    bytecode::{self, BytecodeError, Reader},
+    observer::{Control, VmObserver},
+    varint, ExecutionFault, Instruction, Memory,
+};
+use std::{error, fmt, fs, path};
+
+const MAGIC: &[u8] = b"AOTR";
+const VERSION: u8 = 2;
+
+/// One executed instruction.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Step {
+    pub index: isize,
+    pub instruction: Instruction,
+    pub accumulator_before: isize,
+    pub accumulator_after: isize,
+}
+
+impl Step {
+    /// Where execution went after this step.
+    pub fn next_index(&self) -> isize {
+        match self.instruction {
+            Instruction::Jump(j) => self.index + j,
+            _ => self.index + 1,
+        }
+    }
+}
+
+/// Every step of a run, and the memory it started from.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Trace {
+    pub start: Memory,
+    pub steps: Vec<Step>,
+}
+
+impl Trace {
+    pub fn new(start: Memory) -> Self {
+        Self {
+            start,
+            steps: Vec::new(),
+        }
+    }
+    /// Execute the instruction at `memory.index`, recording it if it succeeds.
+    pub fn execute(&mut self, memory: Memory) -> Result<Memory, ExecutionFault> {
+        let (index, accumulator_before) = (memory.index, memory.accumulator);
+        let after = memory.try_execute_instruction()?;
+        self.after_step(index, accumulator_before, &after);
+        Ok(after)
+    }
+    /// The memory after `position` steps, or `None` if the trace isn't that long.
+    /// Its `inputs` are those the run started with - `in` steps can be read from `steps` instead.
+    pub fn memory_at(&self, position: usize) -> Option<Memory> {
+        let (index, accumulator) = match position {
+            0 => (self.start.index, self.start.accumulator),
+            p => {
+                let step = self.steps.get(p - 1)?;
+                (step.next_index(), step.accumulator_after)
+            }
+        };
+        Some(Memory {
+            instructions: self.start.instructions.clone(),
+            accumulator,
+            index,
+            inputs: self.start.inputs.clone(),
+        })
+    }
+    /// The first step at which `self` and `other` differ, or `None` if they're the same run.
+    /// If one run is a prefix of the other, they diverge where the shorter one stops.
+    pub fn divergence(&self, other: &Trace) -> Option<usize> {
+        let mut pairs = self.steps.iter().zip(other.steps.iter());
+        match pairs.position(|(a, b)| a != b) {
+            Some(position) => Some(position),
+            None if self.steps.len() != other.steps.len() => {
+                Some(self.steps.len().min(other.steps.len()))
+            }
+            None => None,
+        }
+    }
+    /// The whole starting memory, as `Memory::to_bytes` stores it, then only indices and
+    /// accumulator changes for each step - everything else is recovered from the program on
+    /// the way back in.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytecode::write_memory(&mut bytes, &self.start);
+        varint::write_unsigned(&mut bytes, self.steps.len() as u64);
+        for step in self.steps.iter() {
+            varint::write_signed(&mut bytes, step.index as i64);
+            varint::write_signed(
+                &mut bytes,
+                step.accumulator_after.wrapping_sub(step.accumulator_before) as i64,
+            );
+        }
+        bytes
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, TraceError> {
+        let mut reader = Reader::new(bytes);
+        reader.header(MAGIC, VERSION)?;
+        let mut trace = Trace::new(bytecode::read_memory(&mut reader)?);
+        // Re-run the program alongside the recording, to check that it really happened.
+        // Inputs may have been queued during the run, so aren't taken from the start - an `in`
+        // step's accumulator change says what was read.
+        let mut memory = trace.start.clone();
+        // An index and a delta
+        for step in 0..reader.length(2)? {
+            let (index, delta) = (reader.signed()?, reader.signed()?);
+            let accumulator = memory.accumulator;
+            if let Some(Instruction::Input(_)) = memory.instructions.get(memory.index as usize) {
+                memory.inputs = vec![accumulator.wrapping_add(delta)].into();
+            }
+            memory = match trace.execute(memory) {
+                Ok(memory) if index == trace.steps[step].index => memory,
+                _ => return Err(TraceError::Inconsistent { step }),
+            };
+            if memory.accumulator.wrapping_sub(accumulator) != delta {
+                return Err(TraceError::Inconsistent { step });
+            }
+        }
+        match reader.is_empty() {
+            true => Ok(trace),
+            false => Err(TraceError::TrailingBytes),
+        }
+    }
+    pub fn to_file<P: AsRef<path::Path>>(&self, path: P) -> Result<(), Box<dyn error::Error>> {
+        Ok(fs::write(path, self.to_bytes())?)
+    }
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> Result<Self, Box<dyn error::Error>> {
+        Ok(Self::from_bytes(&fs::read(path)?)?)
+    }
+}
+
+/// Records every step of a run through `Memory::run_observed`.
+impl VmObserver for Trace {
+    fn after_step(&mut self, index: isize, accumulator: isize, memory: &Memory) -> Control {
+        self.steps.push(Step {
+            index,
+            instruction: memory.instructions[index as usize].clone(),
+            accumulator_before: accumulator,
+            accumulator_after: memory.accumulator,
+        });
+        Control::Continue
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum TraceError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    Overflow,
+    BadOpcode(u8),
+    TrailingBytes,
+    /// The recorded step doesn't match what the program does.
+    Inconsistent {
+        step: usize,
+    },
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::BadMagic => write!(f, "Not a trace file"),
+            TraceError::UnsupportedVersion(v) => write!(f, "Unsupported trace version {}", v),
+            TraceError::Truncated => write!(f, "Trace is truncated"),
+            TraceError::Overflow => write!(f, "Trace contains a value too large for this platform"),
+            TraceError::BadOpcode(o) => write!(f, "Unknown opcode {}", o),
+            TraceError::TrailingBytes => write!(f, "Unexpected bytes after the end of the trace"),
+            TraceError::Inconsistent { step } => {
+                write!(f, "Step {} doesn't match the recorded program", step)
+            }
+        }
+    }
+}
+
+impl error::Error for TraceError {}
+
+impl From<BytecodeError> for TraceError {
+    fn from(e: BytecodeError) -> Self {
+        match e {
+            BytecodeError::BadMagic => TraceError::BadMagic,
+            BytecodeError::UnsupportedVersion(v) => TraceError::UnsupportedVersion(v),
+            BytecodeError::Truncated { .. } => TraceError::Truncated,
+            BytecodeError::Overflow { .. } => TraceError::Overflow,
+            BytecodeError::BadOpcode { opcode, .. } => TraceError::BadOpcode(opcode),
+            BytecodeError::TrailingBytes { .. } => TraceError::TrailingBytes,
+        }
+    }
+}
+
+/// A cursor over a recorded run, which can be moved in either direction.
+#[derive(Debug, Clone)]
+pub struct Replay<'t> {
+    trace: &'t Trace,
+    position: usize,
+}
+
+impl<'t> Replay<'t> {
+    pub fn new(trace: &'t Trace) -> Self {
+        Self { trace, position: 0 }
+    }
+    /// How many steps have been replayed.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+    /// Redo the next step, if there is one.
+    pub fn forward(&mut self) -> Option<&'t Step> {
+        let step = self.trace.steps.get(self.position)?;
+        self.position += 1;
+        Some(step)
+    }
+    /// Undo the last step, if there is one.
+    pub fn back(&mut self) -> Option<&'t Step> {
+        self.position = self.position.checked_sub(1)?;
+        Some(&self.trace.steps[self.position])
+    }
+    /// Jump to just after step `position`. Returns `false` if the trace isn't that long.
+    pub fn seek(&mut self, position: usize) -> bool {
+        match position <= self.trace.steps.len() {
+            true => {
+                self.position = position;
+                true
+            }
+            false => false,
+        }
+    }
+    pub fn memory(&self) -> Memory {
+        self.trace
+            .memory_at(self.position)
+            .expect("Position is always within the trace")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::immutable::{ImmutableMemory, ImmutableStatus};
+
+    const EXAMPLE: &str =
+        "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n";
+
+    fn record(memory: Memory) -> (ImmutableStatus, Trace) {
+        let mut trace = Trace::new(memory.clone());
+        let status = ImmutableMemory::new(memory.instructions).run_traced(&mut trace);
+        (status, trace)
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let (status, trace) = record(EXAMPLE.parse().unwrap());
+        assert!(matches!(status, ImmutableStatus::WouldRevisit(_)));
+        let indices: Vec<isize> = trace.steps.iter().map(|s| s.index).collect();
+        assert_eq!(indices, vec![0, 1, 2, 6, 7, 3]);
+
+        let mut replay = Replay::new(&trace);
+        assert!(replay.seek(6));
+        assert_eq!(replay.memory().accumulator, 5);
+        assert_eq!(replay.memory().index, 4);
+        assert_eq!(replay.forward(), None);
+        assert_eq!(replay.back().map(|s| s.index), Some(3));
+        assert_eq!(replay.back().map(|s| s.accumulator_after), Some(2));
+        assert_eq!(replay.position(), 4);
+        assert_eq!(replay.memory().index, 7);
+        assert!(replay.seek(0));
+        assert_eq!(replay.back(), None);
+        assert_eq!(replay.memory(), EXAMPLE.parse().unwrap());
+        assert!(!replay.seek(7));
+    }
+    #[test]
+    fn serialise() {
+        let (_, trace) = record(EXAMPLE.parse().unwrap());
+        let bytes = trace.to_bytes();
+        assert!(bytes.len() < 48, "{} bytes", bytes.len());
+        assert_eq!(Trace::from_bytes(&bytes), Ok(trace.clone()));
+
+        let path = std::env::temp_dir().join(format!("aocompute-trace-{}", std::process::id()));
+        trace.to_file(&path).unwrap();
+        assert_eq!(Trace::from_file(&path).unwrap(), trace);
+        fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn corrupted() {
+        let (_, trace) = record(EXAMPLE.parse().unwrap());
+        let bytes = trace.to_bytes();
+        assert_eq!(Trace::from_bytes(b"nope"), Err(TraceError::BadMagic));
+        assert_eq!(
+            Trace::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(TraceError::Truncated)
+        );
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(Trace::from_bytes(&extra), Err(TraceError::TrailingBytes));
+        let mut wrong = bytes.clone();
+        // The index of the last step
+        let last = wrong.len() - 2;
+        wrong[last] = 2;
+        assert_eq!(
+            Trace::from_bytes(&wrong),
+            Err(TraceError::Inconsistent { step: 5 })
+        );
+    }
+    #[test]
+    fn inputs_are_recovered() {
+        let mut memory: Memory = "in +0\nacc +1\nout +0\nin +0\n".parse().unwrap();
+        memory.inputs = vec![-7, 3].into();
+        let mut trace = Trace::new(memory.clone());
+        while let Ok(next) = trace.execute(memory) {
+            memory = next;
+        }
+        assert_eq!(trace.steps.len(), 4);
+        assert_eq!(trace.steps[3].accumulator_after, 3);
+        assert_eq!(Trace::from_bytes(&trace.to_bytes()), Ok(trace.clone()));
+        // Including inputs which weren't there at the start
+        let mut memory = trace.start.clone();
+        memory.inputs.clear();
+        let mut later = Trace::new(memory.clone());
+        memory = later.execute(memory).unwrap_err().memory().clone();
+        memory.inputs.push_back(5);
+        later.execute(memory).unwrap();
+        assert_eq!(Trace::from_bytes(&later.to_bytes()), Ok(later));
+    }
+    #[test]
+    fn diverge() {
+        let original: Memory = EXAMPLE.parse().unwrap();
+        let mut patched = original.clone();
+        patched.instructions[7] = Instruction::Noop(-4);
+        let (_, a) = record(original);
+        let (status, b) = record(patched);
+        assert!(matches!(status, ImmutableStatus::Halted(_)));
+        assert_eq!(a.divergence(&b), Some(4));
+        assert_eq!(a.divergence(&a), None);
+        let mut shorter = a.clone();
+        shorter.steps.truncate(3);
+        assert_eq!(a.divergence(&shorter), Some(3));
+    }
+}
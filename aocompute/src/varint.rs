@@ -0,0 +1,80 @@
+// LEB128 varints, with zigzag encoding for signed values so that small negatives stay small.
+
+pub(crate) fn write_unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        match value {
+            0 => return bytes.push(byte),
+            _ => bytes.push(byte | 0x80),
+        }
+    }
+}
+
+pub(crate) fn write_signed(bytes: &mut Vec<u8>, value: i64) {
+    write_unsigned(bytes, ((value << 1) ^ (value >> 63)) as u64)
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub(crate) enum VarintError {
+    /// `bytes` ran out before the last byte of the varint
+    Truncated,
+    /// The value doesn't fit in 64 bits
+    TooLong,
+}
+
+/// Reads from the front of `bytes`, advancing it past what was read.
+pub(crate) fn read_unsigned(bytes: &mut &[u8]) -> Result<u64, VarintError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or(VarintError::Truncated)?;
+        *bytes = rest;
+        let bits = (byte & 0x7f) as u64;
+        if shift == 63 && bits > 1 {
+            return Err(VarintError::TooLong);
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(VarintError::TooLong)
+}
+
+pub(crate) fn read_signed(bytes: &mut &[u8]) -> Result<i64, VarintError> {
+    let value = read_unsigned(bytes)?;
+    Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn round_trip() {
+        for value in [0, 1, -1, 63, -64, 64, 300, -300, i64::MAX, i64::MIN].iter() {
+            let mut bytes = Vec::new();
+            write_signed(&mut bytes, *value);
+            let mut slice = &bytes[..];
+            assert_eq!(read_signed(&mut slice), Ok(*value));
+            assert!(slice.is_empty());
+        }
+        let mut bytes = Vec::new();
+        write_signed(&mut bytes, -1);
+        assert_eq!(bytes, vec![1]);
+    }
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            read_unsigned(&mut &[0x80, 0x80][..]),
+            Err(VarintError::Truncated)
+        );
+        assert_eq!(
+            read_unsigned(&mut &[0xff; 10][..]),
+            Err(VarintError::TooLong)
+        );
+        assert_eq!(
+            read_unsigned(&mut &[0x80; 11][..]),
+            Err(VarintError::TooLong)
+        );
+    }
+}
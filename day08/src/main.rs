@@ -1,5 +1,4 @@
-use aocompute::{immutable::*, *};
-use std::{fs, panic::catch_unwind};
+use aocompute::{immutable::*, repair, *};
 
 const DAY: &str = "08";
 
@@ -11,6 +10,7 @@ fn part1(filepath: &str) -> isize {
     }
 }
 
+#[cfg(test)]
 fn corrupt_at(index: usize, instructions: Vec<Instruction>) -> Vec<Instruction> {
     let mut instructions = instructions;
     match instructions.get(index) {
@@ -30,23 +30,11 @@ fn corrupt_at(index: usize, instructions: Vec<Instruction>) -> Vec<Instruction>
 }
 
 fn part2(filepath: &str) -> isize {
-    let instructions = fs::read_to_string(filepath)
-        .unwrap()
-        .parse::<Memory>()
-        .unwrap()
-        .instructions;
-    for i in 0..instructions.len() {
-        let corrupted_instructions = corrupt_at(i, instructions.clone());
-        let immutable_memory = ImmutableMemory::new(corrupted_instructions);
-        match catch_unwind(|| immutable_memory.run()) {
-            Ok(result) => match result {
-                ImmutableStatus::Halted(m) => return m.memory.accumulator,
-                _ => continue,
-            },
-            _ => continue,
-        }
+    let memory = Memory::from_file(filepath).unwrap();
+    match repair::repairs(&memory).first() {
+        Some(repair) => repair.accumulator,
+        None => panic!("No match found!"),
     }
-    panic!("No match found!")
 }
 
 fn main() {
@@ -58,14 +46,50 @@ fn main() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use aocompute::ranges::{Interval, Ranges};
     #[test]
     fn test_part1() {
         assert_eq!(part1(&format!("../inputs/day{}.txt", DAY)), 1941);
     }
-    // #[test]
-    // fn test_part2() {
-    //     assert_eq!(part2(&format!("../inputs/day{}.txt", DAY)), 1);
-    // }
+    #[test]
+    fn test_part2() {
+        assert_eq!(part2(&format!("../inputs/day{}.txt", DAY)), 2096);
+    }
+    #[test]
+    fn brute_force_part2() {
+        let memory = Memory::from_file(format!("../inputs/day{}.txt", DAY)).unwrap();
+        let halting: Vec<isize> = (0..memory.instructions.len())
+            .filter_map(|i| {
+                let corrupted_instructions = corrupt_at(i, memory.instructions.clone());
+                match ImmutableMemory::new(corrupted_instructions).run() {
+                    ImmutableStatus::Halted(m) => Some(m.memory.accumulator),
+                    _ => None,
+                }
+            })
+            .collect();
+        assert_eq!(halting, vec![2096]);
+    }
+    #[test]
+    fn analysed_part2() {
+        // With only `nop`, `acc` and `jmp` there's one path, so the ranges are exact
+        let memory = Memory::from_file(format!("../inputs/day{}.txt", DAY)).unwrap();
+        let halting: Vec<Interval> = (0..memory.instructions.len())
+            .filter_map(|i| {
+                let flipped = repair::flip(&memory.instructions[i])?;
+                Ranges::patched(&memory, i, &flipped).at_halt()
+            })
+            .collect();
+        assert_eq!(halting, vec![Interval::point(2096)]);
+    }
+    #[test]
+    fn mutated_part2() {
+        let memory = Memory::from_file(format!("../inputs/day{}.txt", DAY)).unwrap();
+        let halted = |status: &Status| matches!(status, Status::Halted(_));
+        match mutate::search(&memory, &mutate::Rules::nop_jmp(), 1, 10_000, halted) {
+            Some((_, Status::Halted(m))) => assert_eq!(m.accumulator, 2096),
+            _ => panic!(),
+        }
+    }
     #[test]
     fn corruption() {
         let before = vec![Instruction::Accumulate(1)];